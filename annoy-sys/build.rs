@@ -2,20 +2,58 @@ use std::{env, path::PathBuf};
 
 use bindgen::{Builder, CargoCallbacks};
 
-#[cfg(target_os = "macos")]
-const CPP_STDLIB: &str = "c++";
+fn main() {
+    // Build scripts are compiled for the host, so look at the target through cargo's environment
+    // rather than with #[cfg(target_os)].
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    if target_arch == "wasm32" && target_os == "unknown" {
+        // Unsupported: src/lib.rs stops the build with a compile_error! for this target, so skip
+        // compiling wrapper.cpp, which would only fail first with a less helpful C++ error.
+        return;
+    }
+    let cpp_stdlib = match target_os.as_str() {
+        "macos" | "ios" | "emscripten" => "c++",
+        _ => "stdc++",
+    };
 
-#[cfg(target_os = "linux")]
-const CPP_STDLIB: &str = "stdc++";
+    // cc already takes the compiler from CXX and adds CXXFLAGS, but before the flags set here.
+    // ANNOY_SYS_CXXFLAGS is added after them, so it can override anything below, e.g. turn
+    // warnings back on with -Wall. Our -std=c++14 is left out if either sets a -std.
+    println!("cargo:rerun-if-env-changed=ANNOY_SYS_CXXFLAGS");
+    println!("cargo:rerun-if-env-changed=CXXFLAGS");
+    let extra_flags = env::var("ANNOY_SYS_CXXFLAGS").unwrap_or_default();
+    let cxxflags = env::var("CXXFLAGS").unwrap_or_default();
+    let sets_std = cxxflags
+        .split_whitespace()
+        .chain(extra_flags.split_whitespace())
+        .any(|flag| flag.starts_with("-std="));
 
-fn main() {
-    cc::Build::new()
+    let mut build = cc::Build::new();
+    build
         .cpp(true)
         .file("wrapper.cpp")
-        .flag("-std=c++14")
-        .cpp_link_stdlib(CPP_STDLIB)
-        .warnings(false)
-        .compile("libannoy.a");
+        .cpp_link_stdlib(cpp_stdlib)
+        .warnings(false);
+    if !sets_std {
+        build.flag("-std=c++14");
+    }
+    // cc follows the profile's opt-level, which leaves Annoy's distance loops at -O2 or -Os in a
+    // release build that asked for less than 3. They're most of the query time, so always use -O3
+    // when optimizing, and leave debug builds at -O0.
+    if env::var("OPT_LEVEL").unwrap() != "0" {
+        build.opt_level(3);
+    }
+    // The lto feature compiles the C++ with -flto. Fat objects keep the library linkable by a
+    // linker that doesn't do LTO; to optimize across the Rust/C++ boundary build with clang and
+    // RUSTFLAGS="-Clinker-plugin-lto".
+    if env::var_os("CARGO_FEATURE_LTO").is_some() {
+        build.flag("-flto").flag_if_supported("-ffat-lto-objects");
+    }
+    for flag in extra_flags.split_whitespace() {
+        build.flag(flag);
+    }
+    build.compile("libannoy.a");
 
     println!("cargo:rerun-if-changed=wrapper.hpp");
     let bindings = Builder::default()
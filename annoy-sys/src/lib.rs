@@ -2,4 +2,14 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
+// wasm32-unknown-unknown isn't supported. Annoy's build is already single-threaded here (see
+// wrapper.cpp), but even loading and querying an index needs libc's mmap and file APIs, which the
+// target doesn't have. build.rs skips the C++ for it so that this is the error a build reports.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+compile_error!(
+    "annoy-sys doesn't support wasm32-unknown-unknown: Annoy needs libc's mmap and file APIs, \
+     even to load and query an index"
+);
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
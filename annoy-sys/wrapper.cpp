@@ -1,42 +1,450 @@
+#include <errno.h>
 #include <stddef.h>
+#include <algorithm>
+#include <exception>
+#include <new>
+#include <queue>
+#include <utility>
+#include <vector>
 #include "annoy/src/kissrandom.h"
 #include "annoy/src/annoylib.h"
 #include "wrapper.hpp"
 
 using namespace Annoy;
 
-typedef ::AnnoyIndex<int32_t, float, ::Angular, ::Kiss64Random, AnnoyIndexSingleThreadedBuildPolicy> AngularIndex;
+typedef ::AnnoyIndex<int32_t, float, ::Angular, ::Kiss64Random, AnnoyIndexSingleThreadedBuildPolicy> AngularIndexBase;
 
+// AnnoyIndex keeps its node storage protected, so subclass it to give the shims below access.
+class AngularIndex : public AngularIndexBase
+{
+public:
+    AngularIndex(int f) : AngularIndexBase(f) {}
+
+    // AnnoyIndex::unload frees the nodes but leaves _built set, which would make the empty index
+    // refuse new items while looking queryable.
+    void unload()
+    {
+        AngularIndexBase::unload();
+        _built = false;
+    }
+
+    // Returns a new in-RAM index with a copy of this one's items and trees (if it has been built or
+    // loaded). The copy isn't backed by a file, even if this index is.
+    AngularIndex *clone() const
+    {
+        AngularIndex *copy = new AngularIndex(_f);
+        bool built = _built || _loaded;
+        int32_t n_nodes = built ? _n_nodes : _n_items;
+        if (n_nodes > 0)
+        {
+            copy->_allocate_size(n_nodes);
+            memcpy(copy->_nodes, _nodes, _s * (size_t)n_nodes);
+        }
+        copy->_n_items = _n_items;
+        copy->_n_nodes = n_nodes;
+        copy->_roots = _roots;
+        copy->_built = built;
+        copy->_seed = _seed;
+        copy->_verbose = _verbose;
+        return copy;
+    }
+
+    // Returns a pointer to the stored vector of item, or NULL if item was never added.
+    const float *item_ptr(int32_t item) const
+    {
+        if (item < 0 || item >= _n_items || _get(item)->n_descendants != 1)
+        {
+            return NULL;
+        }
+        return _get(item)->v;
+    }
+
+    // Whether the node array is a mapping of a file (from load, save or on_disk_build) rather than
+    // heap memory.
+    bool mapped() const
+    {
+        return _loaded || _on_disk;
+    }
+
+    // Whether the node array is the file given to on_disk_build. AnnoyIndex::save does nothing for
+    // such an index and returns true.
+    bool on_disk() const
+    {
+        return _on_disk;
+    }
+
+    // Returns the built (or loaded) node array and its size in bytes, or NULL if there isn't one.
+    const void *nodes(size_t *size) const
+    {
+        if (!_built && !_loaded)
+        {
+            *size = 0;
+            return NULL;
+        }
+        *size = _s * (size_t)_n_nodes;
+        return _nodes;
+    }
+
+    // Grows the node array to fit n items up front, rather than letting add_item grow it by a
+    // factor of 1.3 at a time as ids increase.
+    bool reserve(int32_t n, char **error)
+    {
+        if (_loaded || _built)
+        {
+            set_error_from_string(error, "You can't reserve space in a built or loaded index");
+            return false;
+        }
+        _allocate_size(n);
+        return true;
+    }
+
+    // Adds the n rows of the row-major array w as items first, first + 1, ..., growing the node
+    // array once up front rather than row by row.
+    bool add_items(int32_t first, const float *w, size_t n, char **error)
+    {
+        if (!_loaded)
+        {
+            _allocate_size(first + (int32_t)n);
+        }
+        for (size_t i = 0; i < n; i++)
+        {
+            if (!add_item(first + (int32_t)i, w + i * _f, error))
+            {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    // Queries on an index without trees would quietly return nothing, so make them an error.
+    bool check_queryable(char **error) const
+    {
+        if (!_built && !_loaded)
+        {
+            set_error_from_string(error, "You can't query an index that hasn't been built or loaded");
+            return false;
+        }
+        return true;
+    }
+
+    // A copy of AnnoyIndex::build that builds the trees one at a time, calling progress after each.
+    // The single-threaded build policy builds every tree in one thread_build call, drawing from one
+    // random number generator seeded with _seed, so this does the same, inlining thread_build's
+    // loop, to build exactly the forest build would.
+    bool build_with_progress(int q, annoy_angular_progress_fn progress, void *user_data, char **error)
+    {
+        if (_loaded)
+        {
+            set_error_from_string(error, "You can't build a loaded index");
+            return false;
+        }
+        if (_built)
+        {
+            set_error_from_string(error, "You can't build a built index");
+            return false;
+        }
+        Angular::template preprocess<float, int32_t, Node>(_nodes, _s, _n_items, _f);
+        _n_nodes = _n_items;
+
+        AnnoyIndexSingleThreadedBuildPolicy policy;
+        Kiss64Random random(_seed);
+        for (int i = 0; q == -1 ? _n_nodes < 2 * _n_items : i < q; i++)
+        {
+            std::vector<int32_t> indices;
+            for (int32_t j = 0; j < _n_items; j++)
+            {
+                // Ids that were never added are zeroed nodes, which thread_build leaves out too.
+                if (_get(j)->n_descendants >= 1)
+                {
+                    indices.push_back(j);
+                }
+            }
+            _roots.push_back(_make_tree(indices, true, random, policy));
+            progress(user_data, i + 1);
+        }
+
+        // Also, copy the roots into the last segment of the array
+        // This way we can load them faster without reading the whole file
+        _allocate_size(_n_nodes + (int32_t)_roots.size());
+        for (size_t i = 0; i < _roots.size(); i++)
+        {
+            memcpy(_get(_n_nodes + (int32_t)i), _get(_roots[i]), _s);
+        }
+        _n_nodes += _roots.size();
+
+        if (_on_disk)
+        {
+            if (!remap_memory_and_truncate(&_nodes, _fd, _s * (size_t)_nodes_size, _s * (size_t)_n_nodes))
+            {
+                set_error_from_errno(error, "Unable to truncate");
+                return false;
+            }
+            _nodes_size = _n_nodes;
+        }
+        _built = true;
+        return true;
+    }
+
+    // Writes the mapped nodes of an on_disk_build index back to its file and waits for the file
+    // to reach the disk. In-RAM and loaded (read-only) indexes have nothing to flush.
+    bool flush(char **error)
+    {
+        if (!_on_disk)
+        {
+            return true;
+        }
+        if (msync(_nodes, _s * (size_t)_nodes_size, MS_SYNC) != 0 || fsync(_fd) != 0)
+        {
+            set_error_from_errno(error, "Unable to flush index");
+            return false;
+        }
+        return true;
+    }
+
+    // The exact n nearest items to v, found by computing the distance to every item rather than
+    // searching the trees. Works whether or not the index has been built.
+    void get_nns_exact(const float *v, size_t n, std::vector<int32_t> *result, std::vector<float> *distances) const
+    {
+        std::vector<char> v_node_buffer(_s);
+        Node *v_node = (Node *)v_node_buffer.data();
+        Angular::zero_value(v_node);
+        memcpy(v_node->v, v, sizeof(float) * _f);
+        Angular::init_node(v_node, _f);
+
+        std::vector<std::pair<float, int32_t>> nns_dist;
+        for (int32_t i = 0; i < _n_items; i++)
+        {
+            // Ids below _n_items that were never added are zeroed nodes.
+            if (_get(i)->n_descendants == 1)
+            {
+                nns_dist.push_back(std::make_pair(Angular::distance(v_node, _get(i), _f), i));
+            }
+        }
+
+        size_t m = nns_dist.size();
+        size_t p = n < m ? n : m;
+        std::partial_sort(nns_dist.begin(), nns_dist.begin() + p, nns_dist.end());
+        for (size_t i = 0; i < p; i++)
+        {
+            distances->push_back(Angular::normalized_distance(nns_dist[i].first));
+            result->push_back(nns_dist[i].second);
+        }
+    }
+
+    // A copy of AnnoyIndex::_get_all_nns that also counts how much of the forest it searched:
+    // the tree nodes it popped off the queue, how many of those were roots and the number of
+    // candidate items (including duplicates across trees) it gathered before ranking them.
+    void get_nns_by_vector_with_stats(const float *v, size_t n, int search_k, std::vector<int32_t> *result, std::vector<float> *distances, size_t *nodes_visited, size_t *trees_used, size_t *n_candidates) const
+    {
+        std::vector<char> v_node_buffer(_s);
+        Node *v_node = (Node *)v_node_buffer.data();
+        Angular::zero_value(v_node);
+        memcpy(v_node->v, v, sizeof(float) * _f);
+        Angular::init_node(v_node, _f);
+
+        std::priority_queue<std::pair<float, int32_t>> q;
+        if (search_k == -1)
+        {
+            search_k = n * _roots.size();
+        }
+        const float root_priority = Angular::pq_initial_value<float>();
+        for (size_t i = 0; i < _roots.size(); i++)
+        {
+            q.push(std::make_pair(root_priority, _roots[i]));
+        }
+
+        *nodes_visited = 0;
+        *trees_used = 0;
+        std::vector<int32_t> nns;
+        while (nns.size() < (size_t)search_k && !q.empty())
+        {
+            const std::pair<float, int32_t> &top = q.top();
+            float d = top.first;
+            int32_t i = top.second;
+            Node *nd = _get(i);
+            q.pop();
+            (*nodes_visited)++;
+            // Children are always queued with a finite priority, so only roots still have this one.
+            if (d == root_priority)
+            {
+                (*trees_used)++;
+            }
+            if (nd->n_descendants == 1 && i < _n_items)
+            {
+                nns.push_back(i);
+            }
+            else if (nd->n_descendants <= _K)
+            {
+                const int32_t *dst = nd->children;
+                nns.insert(nns.end(), dst, &dst[nd->n_descendants]);
+            }
+            else
+            {
+                float margin = Angular::margin(nd, v, _f);
+                q.push(std::make_pair(Angular::pq_distance(d, margin, 1), static_cast<int32_t>(nd->children[1])));
+                q.push(std::make_pair(Angular::pq_distance(d, margin, 0), static_cast<int32_t>(nd->children[0])));
+            }
+        }
+        *n_candidates = nns.size();
+
+        std::sort(nns.begin(), nns.end());
+        std::vector<std::pair<float, int32_t>> nns_dist;
+        int32_t last = -1;
+        for (size_t i = 0; i < nns.size(); i++)
+        {
+            int32_t j = nns[i];
+            if (j == last)
+            {
+                continue;
+            }
+            last = j;
+            if (_get(j)->n_descendants == 1)
+            {
+                nns_dist.push_back(std::make_pair(Angular::distance(v_node, _get(j), _f), j));
+            }
+        }
+
+        size_t m = nns_dist.size();
+        size_t p = n < m ? n : m;
+        std::partial_sort(nns_dist.begin(), nns_dist.begin() + p, nns_dist.end());
+        for (size_t i = 0; i < p; i++)
+        {
+            distances->push_back(Angular::normalized_distance(nns_dist[i].first));
+            result->push_back(nns_dist[i].second);
+        }
+    }
+};
+
+// Runs f, which reports failure like Annoy's methods do: by returning false and setting the
+// char ** it's given to an error message. Also records errno, which is only meaningful if f
+// failed in a system call, so clear it first.
+//
+// An exception unwinding into Rust is undefined behavior, so one thrown by f (in practice
+// std::bad_alloc from a std::vector) is reported as a failure too.
+template <typename F>
+static bool with_error(annoy_error *error, F f)
+{
+    errno = 0;
+    try
+    {
+        if (f(&error->message))
+        {
+            return true;
+        }
+        error->code = errno;
+    }
+    catch (const std::bad_alloc &)
+    {
+        set_error_from_string(&error->message, "Out of memory");
+        error->code = ENOMEM;
+    }
+    catch (const std::exception &e)
+    {
+        set_error_from_string(&error->message, e.what());
+        error->code = 0;
+    }
+    catch (...)
+    {
+        set_error_from_string(&error->message, "Unknown C++ exception");
+        error->code = 0;
+    }
+    return false;
+}
+
+// Buffers for the results of get_nns_by_item and get_nns_by_vector, which copy them out to the
+// caller's arrays. Keeping them per thread means a thread serving queries stops allocating them
+// once they've grown to its largest n. Annoy appends to the vectors it's given, so they're cleared
+// first. (Annoy's search still allocates its own priority queue and candidate list on every query.)
+struct QueryScratch
+{
+    std::vector<int32_t> result;
+    std::vector<float> distances;
+};
+
+static QueryScratch &query_scratch()
+{
+    thread_local QueryScratch scratch;
+    scratch.result.clear();
+    scratch.distances.clear();
+    return scratch;
+}
+
+// None of these may let an exception escape. Shims that allocate either report failures through
+// with_error or return NULL; the others only read or copy nodes that are already allocated.
 extern "C"
 {
     void *annoy_angular_create_index(int f)
     {
-        AngularIndex *ptr = new AngularIndex(f);
-        return (void *)ptr;
+        try
+        {
+            return (void *)new AngularIndex(f);
+        }
+        catch (...)
+        {
+            return NULL;
+        }
+    }
+
+    void *annoy_angular_clone_index(void *idx)
+    {
+        auto ptr = (AngularIndex *)idx;
+        try
+        {
+            return (void *)ptr->clone();
+        }
+        catch (...)
+        {
+            return NULL;
+        }
+    }
+
+    bool annoy_angular_add_item(void *idx, int item, float *w, annoy_error *error)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return with_error(error, [&](char **message)
+                          { return ptr->add_item(item, w, message); });
+    }
+
+    bool annoy_angular_add_items(void *idx, int first_item, float *w, size_t n, annoy_error *error)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return with_error(error, [&](char **message)
+                          { return ptr->add_items(first_item, w, n, message); });
+    }
+
+    bool annoy_angular_reserve(void *idx, int n, annoy_error *error)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return with_error(error, [&](char **message)
+                          { return ptr->reserve(n, message); });
     }
 
-    bool annoy_angular_add_item(void *idx, int item, float *w, char **error)
+    bool annoy_angular_build(void *idx, int q, int n_threads, annoy_error *error)
     {
         auto ptr = (AngularIndex *)idx;
-        return ptr->add_item(item, w, error);
+        return with_error(error, [&](char **message)
+                          { return ptr->build(q, n_threads, message); });
     }
 
-    bool annoy_angular_build(void *idx, int q, int n_threads, char **error)
+    bool annoy_angular_build_with_progress(void *idx, int q, annoy_angular_progress_fn progress, void *user_data, annoy_error *error)
     {
         auto ptr = (AngularIndex *)idx;
-        return ptr->build(q, n_threads, error);
+        return with_error(error, [&](char **message)
+                          { return ptr->build_with_progress(q, progress, user_data, message); });
     }
 
-    bool annoy_angular_unbuild(void *idx, char **error)
+    bool annoy_angular_unbuild(void *idx, annoy_error *error)
     {
         auto ptr = (AngularIndex *)idx;
-        return ptr->unbuild(error);
+        return with_error(error, [&](char **message)
+                          { return ptr->unbuild(message); });
     }
 
-    bool annoy_angular_save(void *idx, char *filename, bool prefault, char **error)
+    bool annoy_angular_save(void *idx, char *filename, bool prefault, annoy_error *error)
     {
         auto ptr = (AngularIndex *)idx;
-        return ptr->save(filename, prefault, error);
+        return with_error(error, [&](char **message)
+                          { return ptr->save(filename, prefault, message); });
     }
 
     void annoy_angular_unload(void *idx)
@@ -45,10 +453,11 @@ extern "C"
         ptr->unload();
     }
 
-    bool annoy_angular_load(void *idx, char *filename, bool prefault, char **error)
+    bool annoy_angular_load(void *idx, char *filename, bool prefault, annoy_error *error)
     {
         auto ptr = (AngularIndex *)idx;
-        return ptr->load(filename, prefault, error);
+        return with_error(error, [&](char **message)
+                          { return ptr->load(filename, prefault, message); });
     }
 
     float annoy_angular_get_distance(void *idx, uint32_t i, uint32_t j)
@@ -57,32 +466,91 @@ extern "C"
         return ptr->get_distance((int32_t)i, (int32_t)j);
     }
 
-    size_t annoy_angular_get_nns_by_item(void *idx, uint32_t item, size_t n, int search_k, uint32_t *result, float *distances)
+    bool annoy_angular_get_nns_by_item(void *idx, uint32_t item, size_t n, int search_k, uint32_t *result, float *distances, size_t *n_results, annoy_error *error)
     {
         auto ptr = (AngularIndex *)idx;
-        std::vector<int32_t> resultVector;
-        std::vector<float> distancesVector;
-        ptr->get_nns_by_item((int32_t)item, n, search_k, &resultVector, &distancesVector);
-        for (auto i = 0; i < resultVector.size(); i++)
-        {
-            result[i] = (uint32_t)resultVector[i];
-            distances[i] = distancesVector[i];
-        }
-        return resultVector.size();
+        return with_error(error, [&](char **message)
+                          {
+            if (!ptr->check_queryable(message))
+            {
+                return false;
+            }
+            QueryScratch &scratch = query_scratch();
+            std::vector<int32_t> &resultVector = scratch.result;
+            std::vector<float> &distancesVector = scratch.distances;
+            ptr->get_nns_by_item((int32_t)item, n, search_k, &resultVector, &distancesVector);
+            for (size_t i = 0; i < resultVector.size(); i++)
+            {
+                result[i] = (uint32_t)resultVector[i];
+                distances[i] = distancesVector[i];
+            }
+            *n_results = resultVector.size();
+            return true; });
     }
 
-    size_t annoy_angular_get_nns_by_vector(void *idx, float *w, size_t n, int search_k, uint32_t *result, float *distances)
+    bool annoy_angular_get_nns_by_vector(void *idx, float *w, size_t n, int search_k, uint32_t *result, float *distances, size_t *n_results, annoy_error *error)
     {
         auto ptr = (AngularIndex *)idx;
-        std::vector<int32_t> resultVector;
-        std::vector<float> distancesVector;
-        ptr->get_nns_by_vector(w, n, search_k, &resultVector, &distancesVector);
-        for (auto i = 0; i < resultVector.size(); i++)
-        {
-            result[i] = (uint32_t)resultVector[i];
-            distances[i] = distancesVector[i];
-        }
-        return resultVector.size();
+        return with_error(error, [&](char **message)
+                          {
+            if (!ptr->check_queryable(message))
+            {
+                return false;
+            }
+            QueryScratch &scratch = query_scratch();
+            std::vector<int32_t> &resultVector = scratch.result;
+            std::vector<float> &distancesVector = scratch.distances;
+            // Annoy skips converting the distances of the results if it isn't given a vector for
+            // them.
+            ptr->get_nns_by_vector(w, n, search_k, &resultVector, distances ? &distancesVector : NULL);
+            for (size_t i = 0; i < resultVector.size(); i++)
+            {
+                result[i] = (uint32_t)resultVector[i];
+                if (distances)
+                {
+                    distances[i] = distancesVector[i];
+                }
+            }
+            *n_results = resultVector.size();
+            return true; });
+    }
+
+    bool annoy_angular_get_nns_by_vector_with_stats(void *idx, float *w, size_t n, int search_k, uint32_t *result, float *distances, size_t *n_results, size_t *nodes_visited, size_t *trees_used, size_t *n_candidates, annoy_error *error)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return with_error(error, [&](char **message)
+                          {
+            if (!ptr->check_queryable(message))
+            {
+                return false;
+            }
+            std::vector<int32_t> resultVector;
+            std::vector<float> distancesVector;
+            ptr->get_nns_by_vector_with_stats(w, n, search_k, &resultVector, &distancesVector, nodes_visited, trees_used, n_candidates);
+            for (size_t i = 0; i < resultVector.size(); i++)
+            {
+                result[i] = (uint32_t)resultVector[i];
+                distances[i] = distancesVector[i];
+            }
+            *n_results = resultVector.size();
+            return true; });
+    }
+
+    bool annoy_angular_get_nns_exact(void *idx, float *w, size_t n, uint32_t *result, float *distances, size_t *n_results, annoy_error *error)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return with_error(error, [&](char **)
+                          {
+            std::vector<int32_t> resultVector;
+            std::vector<float> distancesVector;
+            ptr->get_nns_exact(w, n, &resultVector, &distancesVector);
+            for (size_t i = 0; i < resultVector.size(); i++)
+            {
+                result[i] = (uint32_t)resultVector[i];
+                distances[i] = distancesVector[i];
+            }
+            *n_results = resultVector.size();
+            return true; });
     }
 
     uint32_t annoy_angular_get_n_items(void *idx)
@@ -91,6 +559,12 @@ extern "C"
         return ptr->get_n_items();
     }
 
+    int annoy_angular_get_n_trees(void *idx)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return ptr->get_n_trees();
+    }
+
     void annoy_angular_verbose(void *idx, bool v)
     {
         auto ptr = (AngularIndex *)idx;
@@ -103,16 +577,48 @@ extern "C"
         ptr->get_item((int32_t)item, v);
     }
 
+    const float *annoy_angular_get_item_ptr(void *idx, uint32_t item)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return ptr->item_ptr((int32_t)item);
+    }
+
     void annoy_angular_set_seed(void *idx, uint64_t q)
     {
         auto ptr = (AngularIndex *)idx;
         ptr->set_seed(q);
     }
 
-    bool annoy_angular_on_disk_build(void *idx, char *filename, char **error)
+    bool annoy_angular_on_disk_build(void *idx, char *filename, annoy_error *error)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return with_error(error, [&](char **message)
+                          { return ptr->on_disk_build(filename, message); });
+    }
+
+    bool annoy_angular_flush(void *idx, annoy_error *error)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return with_error(error, [&](char **message)
+                          { return ptr->flush(message); });
+    }
+
+    const void *annoy_angular_get_nodes(void *idx, size_t *size)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return ptr->nodes(size);
+    }
+
+    bool annoy_angular_is_mapped(void *idx)
+    {
+        auto ptr = (AngularIndex *)idx;
+        return ptr->mapped();
+    }
+
+    bool annoy_angular_is_on_disk(void *idx)
     {
         auto ptr = (AngularIndex *)idx;
-        return ptr->on_disk_build(filename, error);
+        return ptr->on_disk();
     }
 
     void annoy_angular_free_index(void *idx)
@@ -3,25 +3,50 @@
 
 using namespace Annoy;
 
-typedef ::AnnoyIndex<int32_t, float, ::Angular, ::Kiss64Random, AnnoyIndexSingleThreadedBuildPolicy> AngularIndex;
+// Called by annoy_angular_build_with_progress with its user_data and the number of trees built so
+// far, after each tree.
+typedef void (*annoy_angular_progress_fn)(void *, int);
+
+// Filled in by shims that fail. message is allocated by the shim and must be released with
+// annoy_angular_free_error. code is the errno of a failed system call, or 0 if the failure didn't
+// come from one (e.g. adding an item to a built index). Shims also report C++ exceptions this way,
+// with ENOMEM for std::bad_alloc.
+typedef struct annoy_error
+{
+    char *message;
+    int code;
+} annoy_error;
 
 extern "C"
 {
     void *annoy_angular_create_index(int f);
-    bool annoy_angular_add_item(void *idx, int item, float *w, char **error);
-    bool annoy_angular_build(void *idx, int q, int n_threads, char **error);
-    bool annoy_angular_unbuild(void *idx, char **error);
-    bool annoy_angular_save(void *idx, char *filename, bool prefault, char **error);
+    void *annoy_angular_clone_index(void *idx);
+    bool annoy_angular_add_item(void *idx, int item, float *w, annoy_error *error);
+    bool annoy_angular_add_items(void *idx, int first_item, float *w, size_t n, annoy_error *error);
+    bool annoy_angular_reserve(void *idx, int n, annoy_error *error);
+    bool annoy_angular_build(void *idx, int q, int n_threads, annoy_error *error);
+    bool annoy_angular_build_with_progress(void *idx, int q, annoy_angular_progress_fn progress, void *user_data, annoy_error *error);
+    bool annoy_angular_unbuild(void *idx, annoy_error *error);
+    bool annoy_angular_save(void *idx, char *filename, bool prefault, annoy_error *error);
     void annoy_angular_unload(void *idx);
-    bool annoy_angular_load(void *idx, char *filename, bool prefault, char **error);
+    bool annoy_angular_load(void *idx, char *filename, bool prefault, annoy_error *error);
     float annoy_angular_get_distance(void *idx, uint32_t i, uint32_t j);
-    size_t annoy_angular_get_nns_by_item(void *idx, uint32_t item, size_t n, int search_k, uint32_t *result, float *distances);
-    size_t annoy_angular_get_nns_by_vector(void *idx, float *w, size_t n, int search_k, uint32_t *result, float *distances);
+    bool annoy_angular_get_nns_by_item(void *idx, uint32_t item, size_t n, int search_k, uint32_t *result, float *distances, size_t *n_results, annoy_error *error);
+    // distances may be NULL if the caller only wants the result ids.
+    bool annoy_angular_get_nns_by_vector(void *idx, float *w, size_t n, int search_k, uint32_t *result, float *distances, size_t *n_results, annoy_error *error);
+    bool annoy_angular_get_nns_by_vector_with_stats(void *idx, float *w, size_t n, int search_k, uint32_t *result, float *distances, size_t *n_results, size_t *nodes_visited, size_t *trees_used, size_t *n_candidates, annoy_error *error);
+    bool annoy_angular_get_nns_exact(void *idx, float *w, size_t n, uint32_t *result, float *distances, size_t *n_results, annoy_error *error);
     uint32_t annoy_angular_get_n_items(void *idx);
+    int annoy_angular_get_n_trees(void *idx);
     void annoy_angular_verbose(void *idx, bool v);
     void annoy_angular_get_item(void *idx, uint32_t item, float *v);
+    const float *annoy_angular_get_item_ptr(void *idx, uint32_t item);
     void annoy_angular_set_seed(void *idx, uint64_t seed);
-    bool annoy_angular_on_disk_build(void *idx, char *filename, char **error);
+    bool annoy_angular_on_disk_build(void *idx, char *filename, annoy_error *error);
+    bool annoy_angular_flush(void *idx, annoy_error *error);
+    const void *annoy_angular_get_nodes(void *idx, size_t *size);
+    bool annoy_angular_is_mapped(void *idx);
+    bool annoy_angular_is_on_disk(void *idx);
     void annoy_angular_free_index(void *idx);
     void annoy_angular_free_error(char *error);
 }
@@ -0,0 +1,30 @@
+use annoy::AnnoyAngular;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const N_ITEMS: u32 = 1_000_000;
+const DIMENSION: usize = 8;
+
+fn add_items(reserve: bool) -> AnnoyAngular {
+    let mut index = AnnoyAngular::new(DIMENSION);
+    if reserve {
+        index.reserve(N_ITEMS).unwrap();
+    }
+    let mut vector = [0.0; DIMENSION];
+    for i in 0..N_ITEMS {
+        vector[i as usize % DIMENSION] = i as f32;
+        index.add_item(i, &vector).unwrap();
+    }
+    index
+}
+
+fn bench_add_items(c: &mut Criterion) {
+    let mut group = c.benchmark_group("add_items");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(N_ITEMS as u64));
+    group.bench_function("no_reserve", |b| b.iter(|| add_items(false)));
+    group.bench_function("reserve", |b| b.iter(|| add_items(true)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_add_items);
+criterion_main!(benches);
@@ -0,0 +1,108 @@
+use std::hint::black_box;
+
+use annoy::{
+    testutil::{random_vectors, temp_path},
+    AnnoyAngular,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+const N_ITEMS: usize = 10_000;
+const N_QUERIES: usize = 100;
+const N_RESULTS: usize = 10;
+
+const DIMENSIONS: &[usize] = &[32, 128];
+const N_TREES: &[i32] = &[10, 50];
+const SEARCH_KS: &[i32] = &[-1, 1_000, 10_000];
+
+fn build_index(vectors: &[Vec<f32>], n_trees: i32) -> AnnoyAngular {
+    let mut index = AnnoyAngular::new(vectors[0].len());
+    for (i, vector) in vectors.iter().enumerate() {
+        index.add_item(i as u32, vector).unwrap();
+    }
+    index.build(n_trees).unwrap();
+    index
+}
+
+fn bench_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    for &dimension in DIMENSIONS {
+        let vectors = random_vectors(N_ITEMS, dimension, 1);
+        for &n_trees in N_TREES {
+            group.bench_with_input(
+                BenchmarkId::new(format!("dimension={dimension}"), n_trees),
+                &n_trees,
+                |b, &n_trees| b.iter(|| build_index(&vectors, n_trees)),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_query(c: &mut Criterion) {
+    for &dimension in DIMENSIONS {
+        let vectors = random_vectors(N_ITEMS, dimension, 1);
+        let queries = random_vectors(N_QUERIES, dimension, 2);
+        for &n_trees in N_TREES {
+            let index = build_index(&vectors, n_trees);
+            let mut group =
+                c.benchmark_group(format!("query/dimension={dimension}/n_trees={n_trees}"));
+            group.throughput(Throughput::Elements(N_QUERIES as u64));
+            for &search_k in SEARCH_KS {
+                group.bench_with_input(
+                    BenchmarkId::new("search_k", search_k),
+                    &search_k,
+                    |b, &search_k| {
+                        b.iter(|| {
+                            for query in &queries {
+                                black_box(
+                                    index
+                                        .get_nearest_by_vector(query, N_RESULTS, search_k)
+                                        .unwrap(),
+                                );
+                            }
+                        })
+                    },
+                );
+            }
+            group.finish();
+        }
+    }
+}
+
+// The first query after loading an index pays for faulting in the pages it touches, unless they
+// were already read in with a.warm(). The file stays in the page cache across iterations, so this
+// measures the page faults of a fresh mapping rather than disk reads.
+fn bench_first_query(c: &mut Criterion) {
+    let dimension = 128;
+    let vectors = random_vectors(N_ITEMS, dimension, 1);
+    let query = &random_vectors(1, dimension, 2)[0];
+    let path = temp_path("bench-first-query");
+    build_index(&vectors, 50).save(&path).unwrap();
+
+    let mut group = c.benchmark_group("first_query");
+    for warm in [false, true] {
+        group.bench_function(if warm { "warm" } else { "cold" }, |b| {
+            b.iter_batched(
+                || {
+                    let mut index = AnnoyAngular::new(dimension);
+                    index.load(&path).unwrap();
+                    if warm {
+                        index.warm();
+                    }
+                    index
+                },
+                |index| {
+                    black_box(index.get_nearest_by_vector(query, N_RESULTS, -1).unwrap());
+                    index
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+    std::fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(benches, bench_build, bench_query, bench_first_query);
+criterion_main!(benches);
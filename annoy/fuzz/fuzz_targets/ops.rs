@@ -0,0 +1,107 @@
+// Runs a sequence of operations against an index, checking that misuse (adding to a built index,
+// querying an unbuilt one, vectors of the wrong dimension, ...) comes back as an error rather than
+// a crash. Run it from annoy/ with
+//
+//     CXXFLAGS=-fsanitize=address cargo +nightly fuzz run ops
+//
+// so that AddressSanitizer covers the C++ side as well as the Rust side.
+//
+// Inputs are a small bytecode, so that the seed corpus in corpus/ops can be written by hand. The
+// first byte picks the dimension (1 to 8), and the rest is a sequence of ops: an opcode byte
+// followed by its arguments. A vector is a length byte (0 to 16, so it can mismatch the dimension)
+// followed by one byte per component, read as i8 / 16. Running out of bytes ends the run.
+#![no_main]
+
+use std::path::Path;
+
+use annoy::AnnoyAngular;
+use libfuzzer_sys::fuzz_target;
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&b, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(b)
+    }
+
+    fn signed(&mut self) -> Option<i32> {
+        Some(self.byte()? as i8 as i32)
+    }
+
+    fn vector(&mut self) -> Option<Vec<f32>> {
+        let len = self.byte()? % 17;
+        (0..len)
+            .map(|_| Some(self.signed()? as f32 / 16.0))
+            .collect()
+    }
+}
+
+fn run(data: &[u8], path: &Path) -> Option<()> {
+    let mut reader = Reader { data };
+    let dimension = (reader.byte()? % 8) as usize + 1;
+    let mut index = AnnoyAngular::new(dimension);
+    loop {
+        // Results are ignored: any Err is fine, only crashes and sanitizer reports are failures.
+        match reader.byte()? % 12 {
+            0 => {
+                let item = reader.byte()? as u32;
+                let vector = reader.vector()?;
+                let _ = index.add_item(item, &vector);
+            }
+            1 => {
+                let _ = index.build(reader.signed()?);
+            }
+            2 => {
+                let _ = index.unbuild();
+            }
+            3 => {
+                let _ = index.save(path);
+            }
+            4 => {
+                let _ = index.load(path);
+            }
+            5 => index.unload(),
+            6 => index.reset(),
+            7 => {
+                let item = reader.byte()? as u32;
+                let n = reader.byte()? as usize;
+                let search_k = reader.signed()?;
+                let _ = index.get_nearest_by_item(item, n, search_k);
+            }
+            8 => {
+                let n = reader.byte()? as usize;
+                let search_k = reader.signed()?;
+                let vector = reader.vector()?;
+                let _ = index.get_nearest_by_vector(vector, n, search_k);
+            }
+            // get_item_vector and get_distance panic on out of range ids, so stay in range.
+            9 => {
+                let item = reader.byte()? as u32;
+                if item < index.get_n_items() {
+                    index.get_item_vector(item);
+                }
+            }
+            10 => {
+                let (i, j) = (reader.byte()? as u32, reader.byte()? as u32);
+                let n_items = index.get_n_items();
+                if i < n_items && j < n_items {
+                    index.get_distance(i, j);
+                }
+            }
+            _ => {
+                let _ = index.verify();
+            }
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let path = std::env::temp_dir().join(format!("annoy-fuzz-{}.ann", std::process::id()));
+    // Every input starts without a saved index, so load only sees files saved with its dimension.
+    let _ = std::fs::remove_file(&path);
+    run(data, &path);
+});
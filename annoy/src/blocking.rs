@@ -0,0 +1,107 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::AnnoyAngular;
+
+// Async versions of the methods that can block for a long time, for use from a tokio runtime.
+// Each one moves the index onto tokio's blocking thread pool with spawn_blocking, runs the method
+// there, and hands the index back along with the method's result, so the runtime's worker threads
+// keep serving other tasks meanwhile.
+//
+// They take the index by value because the blocking work can't be interrupted: if the future is
+// dropped before it completes (e.g. by a timeout), the work still finishes on the blocking pool
+// and the index is freed afterwards. The index comes back from everything else, including a panic
+// in the blocking task and the task being cancelled by the runtime shutting down.
+impl AnnoyAngular {
+    // a.build_async(n_trees).await is a.build(n_trees) on the blocking thread pool.
+    pub async fn build_async(self, n_trees: i32) -> (Self, anyhow::Result<()>) {
+        self.run_blocking(move |index| index.build(n_trees)).await
+    }
+
+    // a.save_async(fn).await is a.save(fn) on the blocking thread pool.
+    pub async fn save_async(self, p: &Path) -> (Self, anyhow::Result<()>) {
+        let p = p.to_owned();
+        self.run_blocking(move |index| index.save(&p)).await
+    }
+
+    // a.load_async(fn).await is a.load(fn) on the blocking thread pool. Loading only maps the
+    // file, but that can still block on a slow disk, and prefaulting it reads the whole file.
+    pub async fn load_async(self, p: &Path) -> (Self, anyhow::Result<()>) {
+        let p = p.to_owned();
+        self.run_blocking(move |index| index.load(&p)).await
+    }
+
+    // The index stays in a slot shared with the blocking task rather than moving into it, so that
+    // it's still here to return if the task panics or never runs.
+    async fn run_blocking<F>(self, f: F) -> (Self, anyhow::Result<()>)
+    where
+        F: FnOnce(&mut AnnoyAngular) -> anyhow::Result<()> + Send + 'static,
+    {
+        let slot = Arc::new(Mutex::new(Some(self)));
+        let task_slot = Arc::clone(&slot);
+        let joined = tokio::task::spawn_blocking(move || {
+            let mut index = task_slot.lock().unwrap_or_else(PoisonError::into_inner);
+            f(index
+                .as_mut()
+                .expect("index taken while the blocking task ran"))
+        })
+        .await;
+        // The task has finished or been dropped, so it no longer holds the lock.
+        let index = slot
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .expect("index taken while the blocking task ran");
+        let result = joined.unwrap_or_else(|err| Err(err.into()));
+        (index, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_path;
+
+    #[test]
+    fn test_async() -> anyhow::Result<()> {
+        let path = temp_path("async");
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        runtime.block_on(async {
+            let mut a = AnnoyAngular::new(3);
+            a.add_item(0, &[1.0, 0.0, 0.0])?;
+            a.add_item(1, &[0.0, 1.0, 0.0])?;
+            let (a, result) = a.build_async(-1).await;
+            result?;
+            assert!(a.is_built());
+            let (_, result) = a.save_async(&path).await;
+            result?;
+
+            let (b, result) = AnnoyAngular::new(3).load_async(&path).await;
+            result?;
+            assert_eq!(b.get_nearest_by_item(1, 1, -1)?.0, vec![1]);
+            let (b, result) = b.build_async(-1).await;
+            assert!(result.is_err());
+            assert_eq!(b.get_n_items(), 2);
+            anyhow::Ok(())
+        })?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_async_panic_keeps_index() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        runtime.block_on(async {
+            let mut a = AnnoyAngular::new(3);
+            a.add_item(0, &[1.0, 0.0, 0.0])?;
+            a.add_item(1, &[0.0, 1.0, 0.0])?;
+            let (a, result) = a.run_blocking(|_| panic!("build exploded")).await;
+            assert!(result.is_err());
+            assert_eq!(a.get_n_items(), 2);
+            assert_eq!(a.get_item_vector(1), vec![0.0, 1.0, 0.0]);
+            anyhow::Ok(())
+        })
+    }
+}
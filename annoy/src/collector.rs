@@ -0,0 +1,93 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::AnnoyAngular;
+
+// A Collector stages items for an index from any thread. It's cheap to clone, so give each
+// producer thread its own clone and drop it once that thread is done adding items.
+#[derive(Clone)]
+pub struct Collector {
+    sender: Sender<(u32, Vec<f32>)>,
+    dimension: usize,
+}
+
+// The receiving end of a set of Collectors, consumed by AnnoyAngular::build_from_collector.
+pub struct CollectorDrain {
+    receiver: Receiver<(u32, Vec<f32>)>,
+}
+
+impl Collector {
+    // c.add_item(i, v) stages item i with vector v. The vector's dimension is checked here rather
+    // than when the item is drained into the index.
+    pub fn add_item(&self, item: u32, vector: Vec<f32>) -> anyhow::Result<()> {
+        anyhow::ensure!(vector.len() == self.dimension);
+        self.sender
+            .send((item, vector))
+            .map_err(|_| anyhow::anyhow!("add_item failed: the collector's drain was dropped"))
+    }
+}
+
+impl AnnoyAngular {
+    // a.builder_channel() returns a Collector for staging items from other threads along with the
+    // CollectorDrain that a.build_from_collector(drain, n_trees) consumes.
+    pub fn builder_channel(&self) -> (Collector, CollectorDrain) {
+        let (sender, receiver) = mpsc::channel();
+        let collector = Collector {
+            sender,
+            dimension: self.dimension,
+        };
+        (collector, CollectorDrain { receiver })
+    }
+
+    // a.build_from_collector(drain, n_trees) adds items to the index as they arrive from the
+    // drain's collectors and builds n_trees trees once every collector has been dropped. Since it
+    // blocks until then, make sure the current thread isn't still holding a collector.
+    pub fn build_from_collector(
+        &mut self,
+        drain: CollectorDrain,
+        n_trees: i32,
+    ) -> anyhow::Result<()> {
+        for (item, vector) in drain.receiver {
+            self.add_item(item, &vector)?;
+        }
+        self.build(n_trees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_build_from_collector() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        let (collector, drain) = a.builder_channel();
+        thread::scope(|s| {
+            for t in 0..4u32 {
+                let collector = collector.clone();
+                s.spawn(move || {
+                    for i in 0..25 {
+                        let item = t * 25 + i;
+                        collector
+                            .add_item(item, vec![item as f32, 1.0, 0.0])
+                            .unwrap();
+                    }
+                });
+            }
+            drop(collector);
+            a.build_from_collector(drain, 10)
+        })?;
+
+        assert_eq!(a.get_n_items(), 100);
+        assert_eq!(a.get_item_vector(42), vec![42.0, 1.0, 0.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_collector_dimension() {
+        let a = AnnoyAngular::new(3);
+        let (collector, _drain) = a.builder_channel();
+        assert!(collector.add_item(0, vec![1.0, 0.0]).is_err());
+    }
+}
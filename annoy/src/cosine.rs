@@ -0,0 +1,152 @@
+use std::path::Path;
+
+use crate::{normalized, AngularDistance, AnnoyAngular};
+
+// AnnoyCosine is an index for cosine distance, 1 - cos(u, v), which is what most embedding models
+// are compared by. It's an AnnoyAngular underneath: vectors are normalized as they're added and
+// queried, and query distances are converted from Annoy's sqrt(2 - 2 cos) to 1 - cos. Rankings are
+// the same as the angular metric's. Zero vectors have no direction, so they're rejected.
+#[derive(Debug)]
+pub struct AnnoyCosine {
+    index: AnnoyAngular,
+}
+
+impl AnnoyCosine {
+    // AnnoyCosine::new(f) returns a new, empty index of f-dimensional vectors.
+    pub fn new(dimension: usize) -> Self {
+        Self {
+            index: AnnoyAngular::new(dimension),
+        }
+    }
+
+    // a.add_item(i, v) adds item i with vector v, stored at unit length.
+    pub fn add_item(&mut self, item: u32, vector: &[f32]) -> anyhow::Result<()> {
+        self.index.add_item_normalized(item, vector)
+    }
+
+    // a.build(n_trees) builds a forest of n_trees trees, like AnnoyAngular::build.
+    pub fn build(&mut self, n_trees: i32) -> anyhow::Result<()> {
+        self.index.build(n_trees)
+    }
+
+    // a.save(fn) saves the index to disk. The file is an ordinary angular index.
+    pub fn save(&mut self, p: &Path) -> anyhow::Result<()> {
+        self.index.save(p)
+    }
+
+    // a.load(fn) loads an index saved by AnnoyCosine::save (or an angular index of normalized
+    // vectors).
+    pub fn load(&mut self, p: &Path) -> anyhow::Result<()> {
+        self.index.load(p)
+    }
+
+    // a.get_nearest_by_item(i, n, search_k) returns the n closest items to item i and their cosine
+    // distances, closest first.
+    pub fn get_nearest_by_item(
+        &self,
+        item: u32,
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<(Vec<u32>, Vec<f32>)> {
+        let (results, distances) = self.index.get_nearest_by_item(item, n, search_k)?;
+        Ok((results, to_cosine_distances(distances)))
+    }
+
+    // a.get_nearest_by_vector(v, n, search_k) returns the n closest items to v and their cosine
+    // distances, closest first.
+    pub fn get_nearest_by_vector(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<(Vec<u32>, Vec<f32>)> {
+        let (results, distances) =
+            self.index
+                .get_nearest_by_vector(normalized(vector)?, n, search_k)?;
+        Ok((results, to_cosine_distances(distances)))
+    }
+
+    // a.get_distance(i, j) returns the cosine distance between items i and j.
+    pub fn get_distance(&self, i: u32, j: u32) -> f32 {
+        self.index.get_distance(i, j).to_cosine_distance()
+    }
+
+    // a.get_item_vector(i) returns item i's vector, normalized.
+    pub fn get_item_vector(&self, item: u32) -> Vec<f32> {
+        self.index.get_item_vector(item)
+    }
+
+    // a.get_n_items() returns the number of items in the index.
+    pub fn get_n_items(&self) -> u32 {
+        self.index.get_n_items()
+    }
+
+    // a.get_n_trees() returns the number of trees in the index.
+    pub fn get_n_trees(&self) -> u32 {
+        self.index.get_n_trees()
+    }
+
+    // a.as_angular() gives access to the underlying angular index, e.g. for its other query
+    // methods. Its distances are angular, not cosine.
+    pub fn as_angular(&self) -> &AnnoyAngular {
+        &self.index
+    }
+}
+
+fn to_cosine_distances(distances: Vec<f32>) -> Vec<f32> {
+    distances
+        .into_iter()
+        .map(|d| AngularDistance(d).to_cosine_distance())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_vectors;
+
+    #[test]
+    fn test_cosine_matches_normalized_angular() -> anyhow::Result<()> {
+        let vectors = random_vectors(200, 8, 1);
+        let mut cosine = AnnoyCosine::new(8);
+        let mut angular = AnnoyAngular::new(8);
+        for (i, vector) in vectors.iter().enumerate() {
+            cosine.add_item(i as u32, vector)?;
+            angular.add_item(i as u32, &normalized(vector)?)?;
+        }
+        cosine.build(10)?;
+        angular.build(10)?;
+
+        for query in random_vectors(10, 8, 2) {
+            let (cosine_results, cosine_distances) =
+                cosine.get_nearest_by_vector(&query, 10, -1)?;
+            let (angular_results, angular_distances) =
+                angular.get_nearest_by_vector(normalized(&query)?, 10, -1)?;
+            assert_eq!(cosine_results, angular_results);
+            for (&c, &a) in cosine_distances.iter().zip(&angular_distances) {
+                assert!((c - a * a / 2.0).abs() < 1e-6);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cosine_distance() -> anyhow::Result<()> {
+        let mut a = AnnoyCosine::new(2);
+        a.add_item(0, &[2.0, 0.0])?;
+        a.add_item(1, &[0.0, 3.0])?;
+        a.add_item(2, &[-1.0, 0.0])?;
+        assert!(a.add_item(3, &[0.0, 0.0]).is_err());
+        a.build(-1)?;
+
+        assert_eq!(a.get_item_vector(0), vec![1.0, 0.0]);
+        assert!((a.get_distance(0, 1) - 1.0).abs() < 1e-6);
+        assert!((a.get_distance(0, 2) - 2.0).abs() < 1e-6);
+        let (results, distances) = a.get_nearest_by_vector(&[5.0, 0.0], 3, -1)?;
+        assert_eq!(results, vec![0, 1, 2]);
+        assert!(distances[0].abs() < 1e-6);
+        assert!((distances[1] - 1.0).abs() < 1e-6);
+        assert!((distances[2] - 2.0).abs() < 1e-6);
+        Ok(())
+    }
+}
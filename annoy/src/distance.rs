@@ -0,0 +1,142 @@
+use std::cmp::Ordering;
+
+// The distance Annoy reports for the angular metric: sqrt(2 - 2 cos(u, v)), which is the euclidean
+// distance between u and v after normalizing both. It ranges from 0 for vectors pointing the same
+// way to 2 for opposite ones, so smaller is closer and the ordering sorts closest first.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AngularDistance(pub f32);
+
+impl AngularDistance {
+    // d.to_cosine_similarity() converts the distance back to cos(u, v), in [-1, 1].
+    pub fn to_cosine_similarity(self) -> f32 {
+        1.0 - self.0 * self.0 / 2.0
+    }
+
+    // d.to_cosine_distance() converts the distance to 1 - cos(u, v), in [0, 2].
+    pub fn to_cosine_distance(self) -> f32 {
+        self.0 * self.0 / 2.0
+    }
+
+    // d.to_unit(unit) converts the distance to the given unit.
+    pub fn to_unit(self, unit: DistanceUnit) -> f32 {
+        match unit {
+            DistanceUnit::Annoy => self.0,
+            DistanceUnit::CosineDistance => self.to_cosine_distance(),
+            DistanceUnit::CosineSimilarity => self.to_cosine_similarity(),
+        }
+    }
+}
+
+// The unit query distances are reported in, for the query methods that take one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DistanceUnit {
+    // Annoy's own angular distance, sqrt(2 - 2 cos(u, v)). Smaller is closer.
+    #[default]
+    Annoy,
+    // 1 - cos(u, v), in [0, 2]. Smaller is closer.
+    CosineDistance,
+    // cos(u, v), in [-1, 1]. Larger is closer, so results still come back closest first but with
+    // decreasing values.
+    CosineSimilarity,
+}
+
+impl From<AngularDistance> for f32 {
+    fn from(distance: AngularDistance) -> f32 {
+        distance.0
+    }
+}
+
+impl PartialEq for AngularDistance {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for AngularDistance {}
+
+impl PartialOrd for AngularDistance {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AngularDistance {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// normalize(v) scales v to unit (L2) length in place. A zero vector has no direction, so it's an
+// error and v is left unchanged.
+pub fn normalize(vector: &mut [f32]) -> anyhow::Result<()> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    anyhow::ensure!(
+        norm > 0.0 && norm.is_finite(),
+        "Can't normalize a vector with norm {norm}"
+    );
+    for x in vector.iter_mut() {
+        *x /= norm;
+    }
+    Ok(())
+}
+
+// normalized(v) returns a unit length copy of v, failing for a zero vector like normalize.
+pub fn normalized(vector: &[f32]) -> anyhow::Result<Vec<f32>> {
+    let mut vector = vector.to_vec();
+    normalize(&mut vector)?;
+    Ok(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_cosine_similarity() {
+        assert_eq!(AngularDistance(0.0).to_cosine_similarity(), 1.0);
+        assert!(AngularDistance(2f32.sqrt()).to_cosine_similarity().abs() < 1e-6);
+        assert_eq!(AngularDistance(2.0).to_cosine_similarity(), -1.0);
+    }
+
+    #[test]
+    fn test_to_unit() {
+        // u = (1, 0) and v = (1, 1) are 45 degrees apart.
+        let cos = 1.0 / 2f32.sqrt();
+        let d = AngularDistance((2.0 - 2.0 * cos).sqrt());
+        assert_eq!(d.to_unit(DistanceUnit::Annoy), d.0);
+        assert!((d.to_unit(DistanceUnit::CosineDistance) - (1.0 - cos)).abs() < 1e-6);
+        assert!((d.to_unit(DistanceUnit::CosineSimilarity) - cos).abs() < 1e-6);
+
+        assert_eq!(AngularDistance(0.0).to_cosine_distance(), 0.0);
+        assert_eq!(AngularDistance(2.0).to_cosine_distance(), 2.0);
+    }
+
+    #[test]
+    fn test_ord() {
+        let mut distances = vec![
+            AngularDistance(1.5),
+            AngularDistance(0.0),
+            AngularDistance(f32::NAN),
+            AngularDistance(0.5),
+        ];
+        distances.sort();
+        let sorted: Vec<f32> = distances.into_iter().map(f32::from).collect();
+        assert_eq!(sorted[..3], [0.0, 0.5, 1.5]);
+        assert!(sorted[3].is_nan());
+    }
+
+    #[test]
+    fn test_normalize() -> anyhow::Result<()> {
+        assert_eq!(normalized(&[3.0, 4.0])?, vec![0.6, 0.8]);
+
+        let mut v = vec![1.0, 2.0, 3.0, 4.0];
+        normalize(&mut v)?;
+        let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-6);
+
+        let mut zero = vec![0.0, 0.0];
+        assert!(normalize(&mut zero).is_err());
+        assert_eq!(zero, vec![0.0, 0.0]);
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use std::{fmt, path::PathBuf};
+
+// Failures that callers may want to handle specifically. They're returned wrapped in an
+// anyhow::Error, so match on them with err.downcast_ref::<AnnoyError>().
+#[derive(Debug)]
+pub enum AnnoyError {
+    // The index file isn't a whole number of nodes for the expected dimension, so it was saved
+    // with a different dimension (or metric).
+    DimensionMismatch {
+        dimension: usize,
+        file_size: u64,
+    },
+    // Annoy reported a failure. code is the errno of the system call that failed, or 0 if the
+    // failure didn't come from one (e.g. adding an item to a built index).
+    Ffi {
+        operation: &'static str,
+        code: i32,
+        message: String,
+    },
+    // try_load found that the index file isn't a whole index, e.g. because a save was cut short.
+    CorruptIndex {
+        path: PathBuf,
+        reason: String,
+    },
+}
+
+impl fmt::Display for AnnoyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnoyError::DimensionMismatch {
+                dimension,
+                file_size,
+            } => write!(
+                f,
+                "Index file of {file_size} bytes doesn't have dimension {dimension}"
+            ),
+            AnnoyError::Ffi {
+                operation, message, ..
+            } => write!(f, "{operation} failed: {message}"),
+            AnnoyError::CorruptIndex { path, reason } => {
+                write!(f, "Index file {} is corrupt: {reason}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for AnnoyError {}
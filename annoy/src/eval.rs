@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+
+use annoy_sys::annoy_angular_get_nns_exact;
+
+use crate::{check_error, debug_assert_sorted, no_error, AnnoyAngular, Neighbor};
+
+// auto_tune measures recall@RECALL_AT, i.e. how many of each query's 10 true nearest neighbors the
+// approximate query finds.
+const RECALL_AT: usize = 10;
+
+// The parameters auto_tune picked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexConfig {
+    pub n_trees: i32,
+}
+
+impl AnnoyAngular {
+    // a.get_nearest_by_vector_exact(v, n) returns the true n closest items to v, by computing the
+    // distance from v to every item with the same distance function the index uses. It's as slow
+    // as that sounds, so use it as ground truth when measuring the recall of approximate queries,
+    // e.g. to pick search_k, rather than for serving. It doesn't need the index to be built.
+    pub fn get_nearest_by_vector_exact(
+        &self,
+        vector: &[f32],
+        n: usize,
+    ) -> anyhow::Result<Vec<Neighbor>> {
+        anyhow::ensure!(vector.len() == self.dimension);
+        let capacity = n.min(self.get_n_items() as usize);
+        let mut results = Vec::with_capacity(capacity);
+        let mut distances = Vec::with_capacity(capacity);
+        unsafe {
+            let mut num_results = 0;
+            let mut error = no_error();
+            let success = annoy_angular_get_nns_exact(
+                self.ptr,
+                vector.as_ptr() as *mut _,
+                n,
+                results.as_mut_ptr(),
+                distances.as_mut_ptr(),
+                &mut num_results,
+                &mut error,
+            );
+            check_error("get_nearest_by_vector_exact", success, error)?;
+            results.set_len(num_results);
+            distances.set_len(num_results);
+        }
+        debug_assert_sorted(&distances);
+        Ok(results
+            .into_iter()
+            .zip(distances)
+            .map(|(id, distance)| Neighbor { id, distance })
+            .collect())
+    }
+
+    // a.measure_recall(queries, n, search_k) returns the mean recall@n of a's approximate queries:
+    // for each query, the fraction of its true n nearest neighbors that
+    // a.get_nearest_by_vector(query, n, search_k) finds. Use it to pick search_k for production.
+    //
+    // It's expensive: finding each query's true neighbors scans every item (see
+    // get_nearest_by_vector_exact), once per query. To compare several search_k values, run it on
+    // a sample of queries rather than the full query log.
+    pub fn measure_recall(
+        &self,
+        queries: &[Vec<f32>],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<f32> {
+        anyhow::ensure!(!queries.is_empty(), "measure_recall failed: no queries");
+        let exact = self.exact_ids(queries, n)?;
+        self.recall_against(queries, &exact, n, search_k)
+    }
+
+    // AnnoyAngular::auto_tune(vectors, candidate_trees, queries) builds an index of vectors (as
+    // AnnoyAngular::from_vectors does) for each tree count in candidate_trees, measures the recall
+    // of each on the queries and returns the config with the best recall, along with that recall.
+    // Ties go to the fewer trees. Recall is the fraction of each query's 10 true nearest
+    // neighbors that a default search_k query finds, averaged over the queries.
+    //
+    // This is expensive: it builds every candidate index, and finding the true neighbors scans
+    // every vector once per query. Use a representative sample rather than the full data set.
+    pub fn auto_tune(
+        vectors: &[Vec<f32>],
+        candidate_trees: &[i32],
+        queries: &[Vec<f32>],
+    ) -> anyhow::Result<(IndexConfig, f32)> {
+        anyhow::ensure!(!queries.is_empty(), "auto_tune failed: no queries");
+        let mut best: Option<(IndexConfig, f32)> = None;
+        let mut exact = None;
+        for &n_trees in candidate_trees {
+            let index = Self::from_vectors(vectors, n_trees)?;
+            // Every candidate has the same items, so their true neighbors only need finding once.
+            if exact.is_none() {
+                exact = Some(index.exact_ids(queries, RECALL_AT)?);
+            }
+            let recall = index.recall_against(queries, exact.as_ref().unwrap(), RECALL_AT, -1)?;
+            if best.is_none_or(|(config, best_recall)| {
+                recall > best_recall || (recall == best_recall && n_trees < config.n_trees)
+            }) {
+                best = Some((IndexConfig { n_trees }, recall));
+            }
+        }
+        best.ok_or_else(|| anyhow::anyhow!("auto_tune failed: no candidate tree counts"))
+    }
+
+    // The ids of the exact n nearest items for each query.
+    fn exact_ids(&self, queries: &[Vec<f32>], n: usize) -> anyhow::Result<Vec<Vec<u32>>> {
+        queries
+            .iter()
+            .map(|query| {
+                let neighbors = self.get_nearest_by_vector_exact(query, n)?;
+                Ok(neighbors.into_iter().map(|neighbor| neighbor.id).collect())
+            })
+            .collect()
+    }
+
+    // The mean fraction of exact[i] found by an approximate top n query for queries[i].
+    fn recall_against(
+        &self,
+        queries: &[Vec<f32>],
+        exact: &[Vec<u32>],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<f32> {
+        let mut total = 0.0;
+        for (query, exact) in queries.iter().zip(exact) {
+            if exact.is_empty() {
+                total += 1.0;
+                continue;
+            }
+            let (results, _) = self.get_nearest_by_vector(query, n, search_k)?;
+            let found: HashSet<u32> = results.into_iter().collect();
+            let hits = exact.iter().filter(|id| found.contains(id)).count();
+            total += hits as f32 / exact.len() as f32;
+        }
+        Ok(total / queries.len() as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_vectors;
+
+    #[test]
+    fn test_get_nearest_by_vector_exact() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        // Item 2 is a hole and must not show up.
+        a.add_item(3, &[1.0, 1.0, 0.0])?;
+
+        let neighbors = a.get_nearest_by_vector_exact(&[1.0, 0.1, 0.0], 10)?;
+        let ids: Vec<u32> = neighbors.iter().map(|n| n.id).collect();
+        assert_eq!(ids, vec![0, 3, 1]);
+        // The distances are Annoy's angular distance, sqrt(2 - 2 cos).
+        let query_norm = (1.0f32 + 0.01).sqrt();
+        let cosines = [
+            1.0 / query_norm,
+            1.1 / (query_norm * 2f32.sqrt()),
+            0.1 / query_norm,
+        ];
+        for (neighbor, cos) in neighbors.iter().zip(cosines) {
+            assert!((neighbor.distance - (2.0 - 2.0 * cos).sqrt()).abs() < 1e-3);
+        }
+
+        // Approximate and exact queries agree when the search covers the whole index.
+        a.build(10)?;
+        let (approximate, _) = a.get_nearest_by_vector([1.0, 0.1, 0.0], 10, 1000)?;
+        assert_eq!(approximate, ids);
+        Ok(())
+    }
+
+    #[test]
+    fn test_measure_recall() -> anyhow::Result<()> {
+        let vectors = random_vectors(1000, 16, 1);
+        let queries = random_vectors(20, 16, 2);
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+
+        // Searching every leaf finds the exact neighbors.
+        assert_eq!(a.measure_recall(&queries, 10, 100_000)?, 1.0);
+        let default = a.measure_recall(&queries, 10, -1)?;
+        let narrow = a.measure_recall(&queries, 10, 10)?;
+        assert!(narrow <= default && default <= 1.0);
+
+        assert!(a.measure_recall(&[], 10, -1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_tune() -> anyhow::Result<()> {
+        let vectors = random_vectors(500, 8, 1);
+        let queries = random_vectors(20, 8, 2);
+
+        let (config, recall) = AnnoyAngular::auto_tune(&vectors, &[1, 20], &queries)?;
+        assert_eq!(config, IndexConfig { n_trees: 20 });
+        assert!(recall > 0.3 && recall <= 1.0);
+
+        assert!(AnnoyAngular::auto_tune(&vectors, &[], &queries).is_err());
+        assert!(AnnoyAngular::auto_tune(&vectors, &[1], &[]).is_err());
+        Ok(())
+    }
+}
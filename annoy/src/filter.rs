@@ -0,0 +1,106 @@
+use std::{collections::HashSet, hash::Hash};
+
+use crate::{AnnoyAngular, Neighbor};
+
+// get_nearest_by_vector_filtered asks the index for this many times n results, to leave enough
+// after filtering when most of them are allowed.
+const FILTER_OVERSAMPLE: usize = 10;
+
+impl AnnoyAngular {
+    // a.get_nearest_by_vector_filtered(v, n, search_k, allow) returns up to n of v's nearest
+    // neighbors for which allow(id) is true, closest first. Annoy has no way to filter during the
+    // search, so this queries for 10 * n results and keeps the allowed ones.
+    //
+    // That works when allow passes a good share of the items. With a very selective allow-list the
+    // 10 * n candidates may hold fewer than n allowed items, or none at all, even though closer
+    // allowed items exist further down the ranking: recall drops with the fraction allowed. Raise
+    // search_k to have each query look at more of the forest, and if the allow-list is small
+    // enough, compare against its items directly (e.g. with a.get_distance) instead.
+    pub fn get_nearest_by_vector_filtered<F: FnMut(u32) -> bool>(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+        mut allow: F,
+    ) -> anyhow::Result<Vec<Neighbor>> {
+        let (results, distances) =
+            self.get_nearest_by_vector(vector, n.saturating_mul(FILTER_OVERSAMPLE), search_k)?;
+        Ok(results
+            .into_iter()
+            .zip(distances)
+            .filter(|&(id, _)| allow(id))
+            .take(n)
+            .map(|(id, distance)| Neighbor { id, distance })
+            .collect())
+    }
+
+    // a.get_nearest_diverse(v, n, search_k, key) returns v's nearest neighbors with at most one
+    // per group, where key(id) is an item's group (e.g. its author): the closest item of each of
+    // up to n groups, closest first. Like get_nearest_by_vector_filtered, it queries for 10 * n
+    // results, so if a few groups hold most of the near neighbors it can return fewer than n.
+    pub fn get_nearest_diverse<K: Eq + Hash, F: Fn(u32) -> K>(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+        key: F,
+    ) -> anyhow::Result<Vec<Neighbor>> {
+        let mut seen = HashSet::new();
+        self.get_nearest_by_vector_filtered(vector, n, search_k, |id| seen.insert(key(id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_vectors;
+
+    #[test]
+    fn test_get_nearest_by_vector_filtered() -> anyhow::Result<()> {
+        let vectors = random_vectors(200, 8, 1);
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+        let query = &random_vectors(1, 8, 2)[0];
+
+        // Allowing everything is a plain query.
+        let (results, _) = a.get_nearest_by_vector(query, 5, 10_000)?;
+        let filtered = a.get_nearest_by_vector_filtered(query, 5, 10_000, |_| true)?;
+        assert_eq!(filtered.iter().map(|n| n.id).collect::<Vec<_>>(), results);
+
+        // With half of the items allowed, the top 5 allowed are the allowed ones among the
+        // unfiltered ranking.
+        let (results, _) = a.get_nearest_by_vector(query, 200, 10_000)?;
+        let even: Vec<u32> = results
+            .into_iter()
+            .filter(|id| id % 2 == 0)
+            .take(5)
+            .collect();
+        let filtered = a.get_nearest_by_vector_filtered(query, 5, 10_000, |id| id % 2 == 0)?;
+        assert_eq!(filtered.iter().map(|n| n.id).collect::<Vec<_>>(), even);
+
+        assert!(a
+            .get_nearest_by_vector_filtered(query, 5, -1, |_| false)?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_diverse() -> anyhow::Result<()> {
+        let vectors = random_vectors(200, 8, 1);
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+        let query = &random_vectors(1, 8, 2)[0];
+
+        // The closest item of each group, in the order the groups first appear in the ranking.
+        let (results, _) = a.get_nearest_by_vector(query, 200, 10_000)?;
+        let mut groups = Vec::new();
+        let mut expected = Vec::new();
+        for id in results {
+            if !groups.contains(&(id % 4)) {
+                groups.push(id % 4);
+                expected.push(id);
+            }
+        }
+        let diverse = a.get_nearest_diverse(query, 10, 10_000, |id| id % 4)?;
+        assert_eq!(diverse.iter().map(|n| n.id).collect::<Vec<_>>(), expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    mem,
+    path::Path,
+};
+
+use crate::AnnoyError;
+
+// Annoy saves an index as its raw node array, without any header. For the angular metric with
+// int32_t ids, each node is n_descendants and two children followed by the vector.
+pub(crate) fn node_size(dimension: usize) -> u64 {
+    (3 * mem::size_of::<i32>() + dimension * mem::size_of::<f32>()) as u64
+}
+
+// The most items a leaf node can list: its children array extends over the space the vector takes
+// in an item node.
+pub(crate) fn max_leaf_size(dimension: usize) -> u64 {
+    (node_size(dimension) - mem::size_of::<i32>() as u64) / mem::size_of::<i32>() as u64
+}
+
+// estimated_build_memory(n_items, f, n_trees) estimates how many bytes an index of n_items
+// f-dimensional items takes once built with n_trees trees (-1 for Annoy's default), which is what
+// build needs in RAM and what save writes to disk.
+//
+// Every item is a node, and each tree adds split nodes and leaf nodes listing up to f + 2 items.
+// Splits are random, so the tree size is an average of about 3 nodes per f + 2 items; expect the
+// real size to differ by some tens of percent. While adding items the node array also grows in
+// steps of 30%, so the peak can be that much higher than the final size.
+pub fn estimated_build_memory(n_items: u64, dimension: usize, n_trees: i32) -> u64 {
+    let tree_nodes = if n_trees < 0 {
+        // Annoy adds trees until there are twice as many nodes as items.
+        n_items
+    } else {
+        n_trees as u64 * nodes_per_tree(n_items, dimension)
+    };
+    (n_items + tree_nodes) * node_size(dimension)
+}
+
+// The nodes estimated_build_memory expects a tree to add, including the copy of its root.
+fn nodes_per_tree(n_items: u64, dimension: usize) -> u64 {
+    (3 * n_items).div_ceil(max_leaf_size(dimension)) + 1
+}
+
+// The most trees for which estimated_build_memory(n_items, f, n_trees) is at most max_bytes.
+pub(crate) fn max_trees_within(n_items: u64, dimension: usize, max_bytes: u64) -> u64 {
+    (max_bytes / node_size(dimension)).saturating_sub(n_items) / nodes_per_tree(n_items, dimension)
+}
+
+// Checks that the index file at p could have been saved with the given dimension. Annoy reads the
+// file with whatever node size it's given, so without this a mismatch silently returns garbage.
+pub(crate) fn check_dimension(p: &Path, dimension: usize) -> anyhow::Result<()> {
+    let file_size = fs::metadata(p)?.len();
+    if file_size % node_size(dimension) != 0 {
+        return Err(AnnoyError::DimensionMismatch {
+            dimension,
+            file_size,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+// Checks that the index file at p looks like a whole index of the given dimension: a whole number
+// of nodes, ending in roots that agree with the items, as read_metadata checks for each candidate
+// dimension.
+pub(crate) fn check_index(p: &Path, dimension: usize) -> anyhow::Result<()> {
+    let corrupt = |reason: String| AnnoyError::CorruptIndex {
+        path: p.to_owned(),
+        reason,
+    };
+    let mut file = File::open(p)?;
+    let file_size = file.metadata()?.len();
+    let s = node_size(dimension);
+    if file_size == 0 {
+        return Err(corrupt("the file is empty".to_string()).into());
+    }
+    if file_size % s != 0 {
+        return Err(corrupt(format!(
+            "{file_size} bytes isn't a whole number of {s} byte nodes"
+        ))
+        .into());
+    }
+    if read_metadata_with_dimension(&mut file, file_size, dimension)?.is_none() {
+        return Err(corrupt("the last nodes aren't the roots of its items".to_string()).into());
+    }
+    Ok(())
+}
+
+// What read_metadata found out about an index file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexMetadata {
+    pub dimension: usize,
+    // The item and tree counts a.get_n_items() and a.get_n_trees() report after loading the file.
+    pub n_items: u32,
+    pub n_trees: u32,
+}
+
+// read_metadata(p) works out the dimension, item count and tree count of the index file at p by
+// reading a few nodes from each end of it, without loading (or mapping) the whole file.
+//
+// Annoy files have no header, so the dimension is inferred: the file is a whole number of nodes of
+// 12 + 4 * f bytes, so the candidates are the f up to MAX_DIMENSION for which that divides its
+// size. For each one, read_metadata checks that the nodes where items and roots would be look like
+// items and roots, the same way load finds the roots. That only reads a few nodes per candidate,
+// and finding the candidates takes a square root of the file size in steps, so it stays quick for a
+// directory of large indexes.
+//
+// A file that fits none of the candidates (e.g. it isn't an angular index) is an error. So is one
+// that looks valid for more than one dimension, which is rare but possible for small files: the
+// error lists the dimensions, and if the right one is known, AnnoyAngular::try_load checks the
+// file against just that one.
+pub fn read_metadata(p: &Path) -> anyhow::Result<IndexMetadata> {
+    let mut file = File::open(p)?;
+    let file_size = file.metadata()?.len();
+    let mut found = Vec::new();
+    for dimension in candidate_dimensions(file_size) {
+        if let Some(metadata) = read_metadata_with_dimension(&mut file, file_size, dimension)? {
+            found.push(metadata);
+        }
+    }
+    match found[..] {
+        [metadata] => Ok(metadata),
+        [] => anyhow::bail!(
+            "read_metadata failed: {} doesn't look like an angular Annoy index",
+            p.display()
+        ),
+        _ => anyhow::bail!(
+            "read_metadata failed: {} could have any of dimensions {:?}",
+            p.display(),
+            found.iter().map(|m| m.dimension).collect::<Vec<_>>()
+        ),
+    }
+}
+
+// read_metadata doesn't consider dimensions above this. Annoy doesn't limit the dimension, but
+// embeddings are at most a few thousand.
+const MAX_DIMENSION: u64 = 1 << 16;
+
+// The dimensions up to MAX_DIMENSION for which a file of file_size bytes is a whole number of
+// nodes, at least two (an item and a root), in increasing order: those of the divisors of the size
+// that are 12 + 4 * f bytes for some f >= 1.
+fn candidate_dimensions(file_size: u64) -> Vec<usize> {
+    let mut dimensions = Vec::new();
+    let mut divisor = 1;
+    while divisor * divisor <= file_size {
+        if file_size.is_multiple_of(divisor) {
+            for s in [divisor, file_size / divisor] {
+                if s >= 16 && s.is_multiple_of(4) && file_size / s >= 2 {
+                    let dimension = (s - 12) / 4;
+                    if dimension <= MAX_DIMENSION {
+                        dimensions.push(dimension as usize);
+                    }
+                }
+            }
+        }
+        divisor += 1;
+    }
+    dimensions.sort_unstable();
+    dimensions.dedup();
+    dimensions
+}
+
+// Reads the first n i32 fields (n_descendants, then the children) of node i from a file of the
+// given node size.
+fn read_node(file: &mut File, node_size: u64, i: u64, n: usize) -> anyhow::Result<Vec<i32>> {
+    let mut buf = vec![0u8; 4 * n];
+    file.seek(SeekFrom::Start(i * node_size))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf
+        .chunks_exact(4)
+        .map(|b| i32::from_ne_bytes(b.try_into().unwrap()))
+        .collect())
+}
+
+// The metadata of the file if it makes sense as an index of the given dimension, or None.
+fn read_metadata_with_dimension(
+    file: &mut File,
+    file_size: u64,
+    dimension: usize,
+) -> anyhow::Result<Option<IndexMetadata>> {
+    let s = node_size(dimension);
+    let n_nodes = file_size / s;
+    let is_node = |c: i32| c >= 0 && (c as u64) < n_nodes;
+
+    // Like AnnoyIndex::load: the roots are the run of nodes at the end with the same number of
+    // descendants as the last one, which is the number of items.
+    let last = read_node(file, s, n_nodes - 1, 3)?;
+    let m = last[0];
+    if m < 1 || m as u64 >= n_nodes {
+        return Ok(None);
+    }
+    let mut roots = vec![last.clone()];
+    for i in (0..n_nodes - 1).rev() {
+        let node = read_node(file, s, i, 3)?;
+        if node[0] != m {
+            break;
+        }
+        roots.push(node);
+    }
+    // load's fix for the last original root preceding the copies of all roots.
+    if roots.len() > 1 && roots[0][1] == roots[roots.len() - 1][1] {
+        roots.pop();
+    }
+
+    // The first m nodes are items (or ids never added, which are zeroed). Checking the first few
+    // is enough to rule out most wrong dimensions.
+    for i in 0..(m as u64).min(8) {
+        if !matches!(read_node(file, s, i, 1)?[0], 0 | 1) {
+            return Ok(None);
+        }
+    }
+    // A root either lists the items, if they fit in a node, or splits them between its two
+    // children. (There can be fewer than m: m counts ids that were never added.) Reading the file
+    // with the wrong node size puts the children somewhere else, so they don't add up.
+    let root_ok = if m as u64 <= max_leaf_size(dimension) {
+        read_node(file, s, n_nodes - 1, 1 + m as usize)?[1..]
+            .iter()
+            .all(|&c| is_node(c))
+    } else {
+        is_node(last[1]) && is_node(last[2]) && {
+            let left = read_node(file, s, last[1] as u64, 1)?[0];
+            let right = read_node(file, s, last[2] as u64, 1)?[0];
+            left >= 1 && right >= 1 && left as i64 + right as i64 <= m as i64
+        }
+    };
+    if !root_ok {
+        return Ok(None);
+    }
+    Ok(Some(IndexMetadata {
+        dimension,
+        n_items: m as u32,
+        n_trees: roots.len() as u32,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testutil::{random_vectors, temp_path},
+        AnnoyAngular,
+    };
+
+    #[test]
+    fn test_estimated_build_memory() {
+        assert_eq!(node_size(3), 24);
+        assert_eq!(max_leaf_size(3), 5);
+        // No trees: just the items.
+        assert_eq!(estimated_build_memory(1000, 3, 0), 24_000);
+        // Each tree has about 3 * 1000 / 5 nodes, plus the copy of its root.
+        assert_eq!(estimated_build_memory(1000, 3, 10), (1000 + 10 * 601) * 24);
+        assert_eq!(estimated_build_memory(1000, 3, -1), 2000 * 24);
+    }
+
+    #[test]
+    fn test_max_trees_within() {
+        let bytes = estimated_build_memory(1000, 3, 10);
+        assert_eq!(max_trees_within(1000, 3, bytes), 10);
+        assert_eq!(max_trees_within(1000, 3, bytes - 1), 9);
+        assert_eq!(max_trees_within(1000, 3, 1000), 0);
+    }
+
+    #[test]
+    fn test_candidate_dimensions() {
+        let brute_force = |file_size: u64| -> Vec<usize> {
+            (1..=MAX_DIMENSION as usize)
+                .filter(|&d| {
+                    file_size.is_multiple_of(node_size(d)) && file_size / node_size(d) >= 2
+                })
+                .collect()
+        };
+        for file_size in [0, 24, 48, 96, 1000, 24_000, 2 * 28 * 101, 123_456_789 * 4] {
+            assert_eq!(candidate_dimensions(file_size), brute_force(file_size));
+        }
+        // An 8 GB file has plenty of divisors, but only ones up to MAX_DIMENSION count.
+        let dimensions = candidate_dimensions(8 << 30);
+        assert!(dimensions.iter().all(|&d| d as u64 <= MAX_DIMENSION));
+    }
+
+    #[test]
+    fn test_read_metadata() -> anyhow::Result<()> {
+        for (dimension, n_items, n_trees) in [(3, 3, 2), (4, 100, 10), (16, 1000, -1), (25, 10, 1)]
+        {
+            let path = temp_path(&format!("read-metadata-{dimension}"));
+            let vectors = random_vectors(n_items, dimension, 1);
+            let mut a = AnnoyAngular::from_vectors(&vectors, n_trees)?;
+            a.save(&path)?;
+            assert_eq!(
+                read_metadata(&path)?,
+                IndexMetadata {
+                    dimension,
+                    n_items: a.get_n_items(),
+                    n_trees: a.get_n_trees(),
+                }
+            );
+            fs::remove_file(&path)?;
+        }
+
+        let path = temp_path("read-metadata-garbage");
+        fs::write(&path, [0xffu8; 96])?;
+        assert!(read_metadata(&path).is_err());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_index() -> anyhow::Result<()> {
+        let path = temp_path("check-index");
+        let is_corrupt = |result: anyhow::Result<()>| {
+            matches!(
+                result.unwrap_err().downcast_ref::<AnnoyError>(),
+                Some(AnnoyError::CorruptIndex { .. })
+            )
+        };
+        let mut a = AnnoyAngular::from_vectors(&random_vectors(100, 4, 1), 10)?;
+        a.save(&path)?;
+        check_index(&path, 4)?;
+
+        let bytes = fs::read(&path)?;
+        fs::write(&path, &bytes[..bytes.len() - 10])?;
+        assert!(is_corrupt(check_index(&path, 4)));
+        fs::write(&path, [])?;
+        assert!(is_corrupt(check_index(&path, 4)));
+        fs::write(&path, [0xffu8; 280])?;
+        assert!(is_corrupt(check_index(&path, 4)));
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
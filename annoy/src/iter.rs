@@ -0,0 +1,187 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::{to_neighbors, AnnoyAngular, DistanceUnit, Neighbor};
+
+// The first batch a NearestIter queries for. Each following batch is twice as large.
+const FIRST_BATCH: usize = 16;
+
+// An iterator over the nearest neighbors of a vector, closest first, from
+// a.iter_nearest_by_vector.
+pub struct NearestIter<'a> {
+    index: &'a AnnoyAngular,
+    vector: Vec<f32>,
+    search_k: i32,
+    // The size of the next batch to query for.
+    n: usize,
+    pending: VecDeque<Neighbor>,
+    seen: HashSet<u32>,
+    done: bool,
+}
+
+impl AnnoyAngular {
+    // a.iter_nearest_by_vector(v, search_k) returns an iterator over v's nearest neighbors, closest
+    // first, for when the number of results needed isn't known up front: take as many as needed
+    // and drop the iterator. It queries in batches, 16 results first and then twice as many each
+    // time it runs out, skipping the ones it already returned.
+    //
+    // With a fixed search_k every batch searches the same candidates, so the iterator yields
+    // exactly the results of one big query, in order. With search_k = -1 each larger batch
+    // searches more of the forest (n * n_trees nodes) and may turn up an item closer than ones
+    // already yielded, which then comes later, out of order.
+    //
+    // Errors a query is bound to hit (an unbuilt index, a vector of the wrong dimension or, with
+    // set_check_finite, a non-finite one) are returned up front. A batch can still fail on its own,
+    // e.g. if Annoy runs out of memory, so the iterator yields Results, and stops after an error.
+    pub fn iter_nearest_by_vector(
+        &self,
+        vector: &[f32],
+        search_k: i32,
+    ) -> anyhow::Result<NearestIter<'_>> {
+        self.ensure_built("iter_nearest_by_vector")?;
+        anyhow::ensure!(vector.len() == self.dimension);
+        self.check_finite("iter_nearest_by_vector", vector)?;
+        Ok(NearestIter {
+            index: self,
+            vector: vector.to_vec(),
+            search_k,
+            n: FIRST_BATCH,
+            pending: VecDeque::new(),
+            seen: HashSet::new(),
+            done: false,
+        })
+    }
+
+    // a.get_nearest_by_vector_paged(v, page_size, page, search_k) returns page number page
+    // (counting from 0) of v's nearest neighbors, page_size per page: results page * page_size up
+    // to (page + 1) * page_size of a query for (page + 1) * page_size results. A page past the end
+    // of the results is empty.
+    //
+    // Every page is a separate query, so pages only fit together if each one ranks the same
+    // candidates. Pass a fixed search_k: with -1, Annoy searches n * n_trees nodes, so each deeper
+    // page searches more of the forest and can turn up items closer than ones already shown on an
+    // earlier page (which are then skipped) or miss ones that were. A fixed search_k also has to be
+    // large enough for the deepest page: it bounds how many candidates there are to rank, so deep
+    // pagination needs a larger one, several times the number of results up to that page.
+    pub fn get_nearest_by_vector_paged(
+        &self,
+        vector: &[f32],
+        page_size: usize,
+        page: usize,
+        search_k: i32,
+    ) -> anyhow::Result<Vec<Neighbor>> {
+        let n = page
+            .checked_add(1)
+            .and_then(|pages| pages.checked_mul(page_size))
+            .ok_or_else(|| {
+                anyhow::anyhow!("get_nearest_by_vector_paged failed: page {page} is too deep")
+            })?;
+        let (results, distances) = self.get_nearest_by_vector(vector, n, search_k)?;
+        Ok(to_neighbors(results, distances, DistanceUnit::Annoy)
+            .into_iter()
+            .skip(page * page_size)
+            .collect())
+    }
+}
+
+impl Iterator for NearestIter<'_> {
+    type Item = anyhow::Result<Neighbor>;
+
+    fn next(&mut self) -> Option<anyhow::Result<Neighbor>> {
+        loop {
+            if let Some(neighbor) = self.pending.pop_front() {
+                return Some(Ok(neighbor));
+            }
+            if self.done {
+                return None;
+            }
+            let n_items = self.index.get_n_items() as usize;
+            let n = self.n.min(n_items);
+            let (results, distances) =
+                match self
+                    .index
+                    .get_nearest_by_vector(&self.vector, n, self.search_k)
+                {
+                    Ok(results) => results,
+                    Err(err) => {
+                        self.done = true;
+                        return Some(Err(err));
+                    }
+                };
+            // Fewer results than asked for means the search has found all it's going to.
+            self.done = results.len() < n || n == n_items;
+            self.n = n.saturating_mul(2);
+            for (id, distance) in results.into_iter().zip(distances) {
+                if self.seen.insert(id) {
+                    self.pending.push_back(Neighbor { id, distance });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_vectors;
+
+    #[test]
+    fn test_get_nearest_by_vector_paged() -> anyhow::Result<()> {
+        let vectors = random_vectors(200, 8, 1);
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+        let query = &random_vectors(1, 8, 2)[0];
+
+        let (results, _) = a.get_nearest_by_vector(query, 200, 10_000)?;
+        let mut paged = Vec::new();
+        for page in 0..15 {
+            let neighbors = a.get_nearest_by_vector_paged(query, 15, page, 10_000)?;
+            assert!(neighbors.len() <= 15);
+            paged.extend(neighbors.into_iter().map(|neighbor| neighbor.id));
+        }
+        assert_eq!(paged, results);
+        assert!(a
+            .get_nearest_by_vector_paged(query, 15, 100, 10_000)?
+            .is_empty());
+        assert!(a
+            .get_nearest_by_vector_paged(query, 15, usize::MAX, 10_000)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_nearest_by_vector() -> anyhow::Result<()> {
+        let vectors = random_vectors(200, 8, 1);
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+        let query = &random_vectors(1, 8, 2)[0];
+
+        // With a fixed search_k, iterating matches a single query for all of the results.
+        let (results, _) = a.get_nearest_by_vector(query, 200, 10_000)?;
+        let iterated: Vec<u32> = a
+            .iter_nearest_by_vector(query, 10_000)?
+            .map(|neighbor| Ok(neighbor?.id))
+            .collect::<anyhow::Result<_>>()?;
+        assert_eq!(iterated, results);
+
+        let first_five: Vec<u32> = a
+            .iter_nearest_by_vector(query, 10_000)?
+            .take(5)
+            .map(|neighbor| Ok(neighbor?.id))
+            .collect::<anyhow::Result<_>>()?;
+        assert_eq!(first_five, results[..5]);
+
+        // With the default search_k there are no duplicates.
+        let ids: Vec<u32> = a
+            .iter_nearest_by_vector(query, -1)?
+            .map(|neighbor| Ok(neighbor?.id))
+            .collect::<anyhow::Result<_>>()?;
+        let unique: HashSet<u32> = ids.iter().copied().collect();
+        assert_eq!(unique.len(), ids.len());
+
+        assert!(a.iter_nearest_by_vector(&[1.0], -1).is_err());
+        let mut b = AnnoyAngular::from_vectors(&vectors, 10)?;
+        b.set_check_finite(true);
+        let mut nan_query = query.clone();
+        nan_query[0] = f32::NAN;
+        assert!(b.iter_nearest_by_vector(&nan_query, -1).is_err());
+        Ok(())
+    }
+}
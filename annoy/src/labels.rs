@@ -0,0 +1,214 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{AnnoyAngular, Neighbor};
+
+// A label (any serde type, e.g. a String) for each item id, stored as a Vec<Option<T>> indexed by
+// id. Ids without a label are None, just like the holes of an index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelStore<T> {
+    labels: Vec<Option<T>>,
+}
+
+impl<T> Default for LabelStore<T> {
+    fn default() -> Self {
+        Self { labels: Vec::new() }
+    }
+}
+
+impl<T> LabelStore<T> {
+    // LabelStore::new() returns an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // s.insert(i, label) sets the label of item i, replacing any it had. Like a.add_item, this
+    // allocates space for max(i) + 1 labels.
+    pub fn insert(&mut self, item: u32, label: T) {
+        let i = item as usize;
+        if i >= self.labels.len() {
+            self.labels.resize_with(i + 1, || None);
+        }
+        self.labels[i] = Some(label);
+    }
+
+    // s.get(i) returns the label of item i, if it has one.
+    pub fn get(&self, item: u32) -> Option<&T> {
+        self.labels.get(item as usize)?.as_ref()
+    }
+}
+
+impl<T: Serialize> LabelStore<T> {
+    // s.save(fn) writes the labels to fn as JSON.
+    pub fn save(&self, p: &Path) -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(File::create(p)?);
+        serde_json::to_writer(&mut writer, &self.labels)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> LabelStore<T> {
+    // LabelStore::load(fn) reads labels written by s.save(fn).
+    pub fn load(p: &Path) -> anyhow::Result<Self> {
+        let labels = serde_json::from_reader(BufReader::new(File::open(p)?))?;
+        Ok(Self { labels })
+    }
+}
+
+// labels_path(fn) is where LabeledAnnoy keeps the labels of the index saved at fn: next to it,
+// with ".labels" appended to the name.
+pub fn labels_path(p: &Path) -> PathBuf {
+    let mut name = OsString::from(p.as_os_str());
+    name.push(".labels");
+    PathBuf::from(name)
+}
+
+// LabeledAnnoy is an AnnoyAngular with a LabelStore alongside it, so that query results come back
+// with each item's label. save and load handle both files: the index at fn and the labels at
+// labels_path(fn).
+#[derive(Debug)]
+pub struct LabeledAnnoy<T> {
+    index: AnnoyAngular,
+    labels: LabelStore<T>,
+}
+
+impl<T> LabeledAnnoy<T> {
+    // LabeledAnnoy::new(f) returns a new, empty index of f-dimensional vectors with no labels.
+    pub fn new(dimension: usize) -> Self {
+        Self {
+            index: AnnoyAngular::new(dimension),
+            labels: LabelStore::new(),
+        }
+    }
+
+    // a.add_item_with_label(i, v, label) adds item i with vector v, like AnnoyAngular::add_item,
+    // and gives it label.
+    pub fn add_item_with_label(
+        &mut self,
+        item: u32,
+        vector: &[f32],
+        label: T,
+    ) -> anyhow::Result<()> {
+        self.index.add_item(item, vector)?;
+        self.labels.insert(item, label);
+        Ok(())
+    }
+
+    // a.build(n_trees) builds the index, like AnnoyAngular::build.
+    pub fn build(&mut self, n_trees: i32) -> anyhow::Result<()> {
+        self.index.build(n_trees)
+    }
+
+    // a.get_nearest_by_vector_with_labels(v, n, search_k) is AnnoyAngular::get_nearest_by_vector
+    // returning each neighbor along with its label.
+    pub fn get_nearest_by_vector_with_labels(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<Vec<(Neighbor, Option<&T>)>> {
+        let (results, distances) = self.index.get_nearest_by_vector(vector, n, search_k)?;
+        Ok(self.with_labels(results, distances))
+    }
+
+    // a.get_nearest_by_item_with_labels(i, n, search_k) is AnnoyAngular::get_nearest_by_item
+    // returning each neighbor along with its label.
+    pub fn get_nearest_by_item_with_labels(
+        &self,
+        item: u32,
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<Vec<(Neighbor, Option<&T>)>> {
+        let (results, distances) = self.index.get_nearest_by_item(item, n, search_k)?;
+        Ok(self.with_labels(results, distances))
+    }
+
+    // a.label(i) returns the label of item i, if it has one.
+    pub fn label(&self, item: u32) -> Option<&T> {
+        self.labels.get(item)
+    }
+
+    // a.labels() returns the label store.
+    pub fn labels(&self) -> &LabelStore<T> {
+        &self.labels
+    }
+
+    // a.as_angular() returns the underlying angular index, e.g. for queries without labels.
+    pub fn as_angular(&self) -> &AnnoyAngular {
+        &self.index
+    }
+
+    fn with_labels(&self, results: Vec<u32>, distances: Vec<f32>) -> Vec<(Neighbor, Option<&T>)> {
+        results
+            .into_iter()
+            .zip(distances)
+            .map(|(id, distance)| (Neighbor { id, distance }, self.labels.get(id)))
+            .collect()
+    }
+}
+
+impl<T: Serialize> LabeledAnnoy<T> {
+    // a.save(fn) saves the index to fn, like AnnoyAngular::save, and the labels to labels_path(fn).
+    pub fn save(&mut self, p: &Path) -> anyhow::Result<()> {
+        self.index.save(p)?;
+        self.labels.save(&labels_path(p))
+    }
+}
+
+impl<T: DeserializeOwned> LabeledAnnoy<T> {
+    // a.load(fn) loads the index at fn, like AnnoyAngular::load, and its labels from
+    // labels_path(fn). An index saved without labels loads with none.
+    pub fn load(&mut self, p: &Path) -> anyhow::Result<()> {
+        let labels_path = labels_path(p);
+        let labels = if fs::exists(&labels_path)? {
+            LabelStore::load(&labels_path)?
+        } else {
+            LabelStore::new()
+        };
+        self.index.load(p)?;
+        self.labels = labels;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_path;
+
+    #[test]
+    fn test_labeled_annoy() -> anyhow::Result<()> {
+        let path = temp_path("labels");
+        let mut a = LabeledAnnoy::new(3);
+        a.add_item_with_label(0, &[1.0, 0.0, 0.0], "x".to_string())?;
+        a.add_item_with_label(2, &[0.0, 1.0, 0.0], "y".to_string())?;
+        a.build(-1)?;
+        a.save(&path)?;
+
+        let mut b = LabeledAnnoy::<String>::new(3);
+        b.load(&path)?;
+        assert_eq!(b.labels(), a.labels());
+        assert_eq!(b.label(1), None);
+        let results = b.get_nearest_by_vector_with_labels(&[0.1, 1.0, 0.0], 2, -1)?;
+        let ids_and_labels: Vec<(u32, Option<&str>)> = results
+            .iter()
+            .map(|(neighbor, label)| (neighbor.id, label.map(|l| l.as_str())))
+            .collect();
+        assert_eq!(ids_and_labels, vec![(2, Some("y")), (0, Some("x"))]);
+        assert_eq!(
+            b.get_nearest_by_item_with_labels(0, 1, -1)?[0].1,
+            Some(&"x".to_string())
+        );
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(labels_path(&path))?;
+        Ok(())
+    }
+}
@@ -1,7 +1,13 @@
 use std::{
-    ffi::{c_char, c_int, c_void, CStr, CString},
-    path::Path,
-    ptr,
+    collections::HashMap,
+    ffi::{c_int, c_void, CStr, CString},
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    mem,
+    ops::Range,
+    path::{Path, PathBuf},
+    ptr, slice,
 };
 
 // TODO:
@@ -10,22 +16,154 @@ use std::{
 // - port accuracy test
 // - builder pattern
 // - change header to use const ptrs where appropraite
-// - get_n_trees
 // - more rusty APIs than -1 isize
+// - no_std query-only core? Loading still needs libstdc++ and libc's mmap underneath the shims,
+//   so this would only drop anyhow/std::path on the Rust side. Probably a separate crate.
+// - wasm32-unknown-unknown, which annoy-sys refuses to compile for (see its lib.rs). It would need
+//   the shims to load an index from bytes in memory instead of mmapping a file.
+// - AnnoyDot for the dot metric. Annoy's DotProduct keeps the vector at f components and puts the
+//   extra coordinate of its inner product to angular transform in a separate dot_factor field of
+//   the node, filled in by build's preprocess. So get_item should keep returning f floats, but
+//   needs a test once the wrapper exists (shims are angular-only, see wrapper.cpp).
+// - rebuild_as::<M>(n_trees) to re-index the same items under another metric, e.g. to compare
+//   angular against euclidean. Needs the generic Annoy<M> (or at least a second metric's shims)
+//   first; with only AnnoyAngular it would just be iter_items into add_item and build.
 //
 // glove-100-angular:
 // num_trees: 100-400, search_k: 100,000
 
 use annoy_sys::*;
 
+// debug_span!(name, fields...) enters a tracing::debug_span! for the rest of the enclosing block
+// when the `tracing` feature is enabled, and does nothing otherwise.
+macro_rules! debug_span {
+    ($name:expr, $($fields:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($name, $($fields)*).entered();
+    };
+}
+
+#[cfg(feature = "tokio")]
+mod blocking;
+mod collector;
+mod cosine;
+mod distance;
+mod error;
+mod eval;
+mod filter;
+mod format;
+mod iter;
+#[cfg(feature = "labels")]
+mod labels;
+#[cfg(feature = "space")]
+mod metric;
+mod readonly;
+#[cfg(feature = "labels")]
+mod recommend;
+mod rerank;
+mod shard;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+
+pub use crate::{
+    collector::{Collector, CollectorDrain},
+    cosine::AnnoyCosine,
+    distance::{normalize, normalized, AngularDistance, DistanceUnit},
+    error::AnnoyError,
+    eval::IndexConfig,
+    format::{estimated_build_memory, read_metadata, IndexMetadata},
+    iter::NearestIter,
+    readonly::ReadOnlyAnnoy,
+    shard::{merge_query, ShardNeighbor},
+};
+
+#[cfg(feature = "labels")]
+pub use crate::labels::{labels_path, LabelStore, LabeledAnnoy};
+#[cfg(feature = "space")]
+pub use crate::metric::AngularMetric;
+#[cfg(feature = "labels")]
+pub use crate::recommend::RecommendationIndex;
+
+// One query result: an item and its distance from the query.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Neighbor {
+    pub id: u32,
+    pub distance: f32,
+}
+
+// How much of the forest a query searched, from a.query_with_stats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    // Tree nodes taken off the search queue. The search stops once it has search_k candidates or
+    // has run out of nodes.
+    pub nodes_visited: usize,
+    // Trees whose root the search visited.
+    pub trees_used: usize,
+    // Candidate items gathered from the leaves (counting duplicates across trees) before they were
+    // ranked by distance.
+    pub n_candidates: usize,
+}
+
+// A summary of an index for monitoring, from a.stats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexStats {
+    pub n_items: u32,
+    pub n_trees: u32,
+    pub dimension: usize,
+    pub metric: &'static str,
+    // Size of the node array: the size of the file for a loaded index, or what save would write
+    // for a built one. Before build it only counts the items added so far.
+    pub bytes: u64,
+}
+
+// Where an index's nodes live, from a.backing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backing {
+    // Heap memory: an index that's being filled in or was built in RAM.
+    Ram,
+    // A memory-mapped file: a loaded index (including one that was saved, since save reloads it)
+    // or one from on_disk_build. Its pages come from the page cache, so a.warm and prefault apply.
+    Mmap,
+}
+
+// How a loaded index is going to be read, for a.load_with_advice. These map to madvise(2) hints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MmapAdvice {
+    // No special treatment (MADV_NORMAL).
+    Normal,
+    // Pages will be read in random order, so don't read ahead (MADV_RANDOM). Suits serving queries.
+    Random,
+    // Pages will be read in order, so read ahead aggressively (MADV_SEQUENTIAL). Suits scanning
+    // every item, e.g. with iter_items.
+    Sequential,
+    // Pages will be needed soon, so start reading them in now (MADV_WILLNEED).
+    WillNeed,
+}
+
 pub struct AnnoyAngular {
     ptr: *mut c_void,
     dimension: usize,
+    // Set once the index has trees, either from build or load, and cleared by unload and reset.
+    built: bool,
+    // Set by on_disk_build_with_scratch: the scratch file being built and where build moves it.
+    publish: Option<(PathBuf, PathBuf)>,
+    // Set by new_with_capacity: item ids must be below it.
+    capacity: Option<u32>,
+    // The file the index is mapped from, after save, load or on_disk_build.
+    path: Option<PathBuf>,
+    // Set by set_check_finite: vectors with NaN or infinite components are errors.
+    check_finite: bool,
 }
 
 impl Drop for AnnoyAngular {
     fn drop(&mut self) {
         if !self.ptr.is_null() {
+            #[cfg(feature = "drop-logging")]
+            tracing::debug!(
+                dimension = self.dimension,
+                n_items = self.get_n_items(),
+                "annoy: freeing index"
+            );
             unsafe {
                 annoy_angular_free_index(self.ptr);
                 self.ptr = ptr::null_mut();
@@ -34,92 +172,738 @@ impl Drop for AnnoyAngular {
     }
 }
 
+// The index is a heap object that only this AnnoyAngular points to, and Annoy doesn't tie it to
+// the thread that created it, so it's safe to move to (or build on) another thread. It isn't Sync:
+// that needs a look at which Annoy methods are really read-only.
+unsafe impl Send for AnnoyAngular {}
+
+impl fmt::Debug for AnnoyAngular {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("AnnoyAngular");
+        s.field("dimension", &self.dimension)
+            .field("metric", &"angular");
+        if self.ptr.is_null() {
+            return s.finish_non_exhaustive();
+        }
+        s.field("built", &self.built)
+            .field("n_items", &self.get_n_items())
+            .field("n_trees", &self.get_n_trees())
+            .finish()
+    }
+}
+
 impl AnnoyAngular {
     // AnnoyIndex(f, metric) returns a new index that's read-write and stores vector
     // of f dimensions. Metric can be "angular", "euclidean", "manhattan", "hamming",
     // or "dot".
+    //
+    // Panics if Annoy can't allocate the index; use try_new to get an error instead.
     pub fn new(dimension: usize) -> Self {
-        let ptr = unsafe { annoy_angular_create_index(dimension as c_int) };
-        Self { ptr, dimension }
+        Self::try_new(dimension).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    // AnnoyAngular::try_new(f) is like AnnoyAngular::new(f), but returns an error instead of
+    // panicking if the index can't be allocated or f doesn't fit in a C int.
+    pub fn try_new(dimension: usize) -> anyhow::Result<Self> {
+        let ptr = try_create_index(dimension)?;
+        Ok(Self {
+            ptr,
+            dimension,
+            built: false,
+            publish: None,
+            capacity: None,
+            path: None,
+            check_finite: false,
+        })
+    }
+
+    // AnnoyAngular::new_with_capacity(f, n) is like AnnoyAngular::new(f) for an index that will
+    // hold exactly the items 0..n: it reserves space for them up front, and adding an item with an
+    // id of n or more is an error instead of silently growing the index.
+    pub fn new_with_capacity(dimension: usize, n_items: u32) -> anyhow::Result<Self> {
+        let mut index = Self::try_new(dimension)?;
+        index.reserve(n_items)?;
+        index.capacity = Some(n_items);
+        Ok(index)
+    }
+
+    // AnnoyAngular::from_vectors(vectors, n_trees) builds an index of n_trees trees with item i
+    // set to vectors[i]. The dimension is taken from the first vector.
+    pub fn from_vectors(vectors: &[Vec<f32>], n_trees: i32) -> anyhow::Result<Self> {
+        let dimension = vectors
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("from_vectors failed: no vectors"))?
+            .len();
+        let mut index = Self::try_new(dimension)?;
+        for (i, vector) in vectors.iter().enumerate() {
+            anyhow::ensure!(
+                vector.len() == dimension,
+                "from_vectors failed: vector {i} has dimension {}, expected {dimension}",
+                vector.len()
+            );
+            index.add_item(i as u32, vector)?;
+        }
+        index.build(n_trees)?;
+        Ok(index)
+    }
+
+    // a.try_clone() returns an independent copy of a with the same items and, if a has been built
+    // or loaded, the same trees. The copy lives in RAM even if a is backed by a file, so this costs
+    // a full copy of every item and tree node in memory.
+    pub fn try_clone(&self) -> anyhow::Result<Self> {
+        anyhow::ensure!(!self.ptr.is_null(), "try_clone failed: the index was freed");
+        let ptr = unsafe { annoy_angular_clone_index(self.ptr) };
+        anyhow::ensure!(!ptr.is_null(), "try_clone failed: couldn't copy the index");
+        Ok(Self {
+            ptr,
+            dimension: self.dimension,
+            built: self.built,
+            publish: None,
+            capacity: self.capacity,
+            path: None,
+            check_finite: self.check_finite,
+        })
+    }
+
+    /// a.as_raw() returns the pointer to a's Annoy index, an `AnnoyIndex<int32_t, float, Angular,
+    /// Kiss64Random, AnnoyIndexSingleThreadedBuildPolicy>` created by annoy-sys, for calling C++
+    /// functions this crate doesn't wrap. a still owns the index and frees it when dropped.
+    ///
+    /// # Safety
+    ///
+    /// The pointer is only valid while a is alive and hasn't been reset or unloaded (which may
+    /// replace the index), and mustn't be freed or passed to from_raw. a keeps track of whether
+    /// the index is built and which file it's mapped from, so anything done through the pointer
+    /// must leave those unchanged, or be followed by the matching a.build, a.load or a.unload.
+    pub unsafe fn as_raw(&self) -> *mut c_void {
+        self.ptr
+    }
+
+    // a.into_raw() gives up ownership of a's Annoy index and returns the pointer to it, which
+    // must then be freed with annoy_angular_free_index, or turned back into an AnnoyAngular with
+    // from_raw.
+    pub fn into_raw(mut self) -> *mut c_void {
+        // Drop does nothing for a null index.
+        mem::replace(&mut self.ptr, ptr::null_mut())
+    }
+
+    /// AnnoyAngular::from_raw(p, f) takes ownership of the Annoy index p, e.g. one returned by
+    /// into_raw, and frees it when dropped. The index counts as built if it has trees, and as
+    /// being in RAM, so current_path() is None even if p was loaded from a file.
+    ///
+    /// # Safety
+    ///
+    /// p must be a non-null index returned by annoy_angular_create_index (or into_raw) with
+    /// dimension f, and not owned by anything else: no other AnnoyAngular may hold it, and it
+    /// mustn't be freed elsewhere.
+    pub unsafe fn from_raw(ptr: *mut c_void, dimension: usize) -> Self {
+        let mut index = Self {
+            ptr,
+            dimension,
+            built: false,
+            publish: None,
+            capacity: None,
+            path: None,
+            check_finite: false,
+        };
+        index.built = index.get_n_trees() > 0;
+        index
     }
 
     // a.add_item(i, v) adds item i (any nonnegative integer) with vector v. Note that
     // it will allocate memory for max(i)+1 items.
     pub fn add_item(&mut self, item: u32, vector: &[f32]) -> anyhow::Result<()> {
         anyhow::ensure!(vector.len() == self.dimension);
-        assert_eq!(vector.len(), self.dimension);
+        self.check_finite("add_item", vector)?;
+        unsafe { self.add_item_unchecked(item, vector) }
+    }
+
+    /// a.add_item_unchecked(i, v) is a.add_item(i, v) without checking that v has a.dimension
+    /// components, for hot loops whose vectors have already been checked.
+    ///
+    /// # Safety
+    ///
+    /// v must have at least the index's dimension components. Annoy reads that many floats from v
+    /// regardless of its length, so a shorter v is an out-of-bounds read (undefined behavior), and
+    /// any components past the dimension are ignored.
+    pub unsafe fn add_item_unchecked(&mut self, item: u32, vector: &[f32]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.built,
+            "add_item failed: can't add items to a built index"
+        );
+        if let Some(capacity) = self.capacity {
+            anyhow::ensure!(
+                item < capacity,
+                "add_item failed: item {item} is out of range for an index with capacity {capacity}"
+            );
+        }
         unsafe {
-            let mut error_ptr: *mut c_char = ptr::null_mut();
+            let mut error = no_error();
             let success = annoy_angular_add_item(
                 self.ptr,
                 item as c_int,
                 vector.as_ptr() as *mut _,
-                &mut error_ptr as *mut _,
+                &mut error,
+            );
+            check_error("add_item", success, error)?;
+        }
+        Ok(())
+    }
+
+    // a.push(v) adds v as the item after the highest id added so far (0 for an empty index) and
+    // returns its id. Since that's a.get_n_items(), push and add_item can be mixed, and the ids
+    // pushed in a row are consecutive.
+    pub fn push(&mut self, vector: &[f32]) -> anyhow::Result<u32> {
+        let item = self.get_n_items();
+        self.add_item(item, vector)?;
+        Ok(item)
+    }
+
+    // a.add_items_from_slice(data, f) adds each row of data, a row-major array of f-dimensional
+    // vectors, as the next item (like a.push) and returns the range of ids they got. The whole
+    // slice goes to Annoy in one call, so data can be e.g. a memory-mapped file of vectors without
+    // copying it into Vecs first. Annoy still copies each row into its own node array.
+    pub fn add_items_from_slice(
+        &mut self,
+        data: &[f32],
+        dimension: usize,
+    ) -> anyhow::Result<Range<u32>> {
+        anyhow::ensure!(
+            !self.built,
+            "add_items_from_slice failed: can't add items to a built index"
+        );
+        anyhow::ensure!(
+            dimension == self.dimension,
+            "add_items_from_slice failed: rows have dimension {dimension}, expected {}",
+            self.dimension
+        );
+        anyhow::ensure!(
+            dimension > 0 && data.len().is_multiple_of(dimension),
+            "add_items_from_slice failed: {} values aren't a whole number of rows",
+            data.len()
+        );
+        self.check_finite("add_items_from_slice", data)?;
+        let first = self.get_n_items();
+        let n_rows = data.len() / dimension;
+        let end = u32::try_from(n_rows)
+            .ok()
+            .and_then(|n| first.checked_add(n))
+            .filter(|&end| c_int::try_from(end).is_ok())
+            .ok_or_else(|| anyhow::anyhow!("add_items_from_slice failed: too many items"))?;
+        if let Some(capacity) = self.capacity {
+            anyhow::ensure!(
+                end <= capacity,
+                "add_items_from_slice failed: items up to {end} are out of range for an index with capacity {capacity}"
+            );
+        }
+        unsafe {
+            let mut error = no_error();
+            let success = annoy_angular_add_items(
+                self.ptr,
+                first as c_int,
+                data.as_ptr() as *mut _,
+                n_rows,
+                &mut error,
             );
-            check_error("add_item", success, error_ptr)?;
+            check_error("add_items", success, error)?;
+        }
+        Ok(first..end)
+    }
+
+    // a.reserve(n) allocates space for items 0..n up front, so that adding items with increasing
+    // ids doesn't repeatedly reallocate. It doesn't change a.get_n_items().
+    pub fn reserve(&mut self, n: u32) -> anyhow::Result<()> {
+        let n = c_int::try_from(n)?;
+        unsafe {
+            let mut error = no_error();
+            let success = annoy_angular_reserve(self.ptr, n, &mut error);
+            check_error("reserve", success, error)?;
         }
         Ok(())
     }
 
+    // a.set_check_finite(true) makes add_item, add_items_from_slice and the get_nearest_by_vector
+    // queries return an error for a vector with a NaN or infinite component. Annoy doesn't check:
+    // such a vector silently poisons the split planes it ends up in, or gives meaningless query
+    // results. It's off by default because it costs a pass over every vector; the _unchecked
+    // methods never check.
+    pub fn set_check_finite(&mut self, check: bool) {
+        self.check_finite = check;
+    }
+
+    fn check_finite(&self, operation: &str, vector: &[f32]) -> anyhow::Result<()> {
+        if !self.check_finite {
+            return Ok(());
+        }
+        match vector.iter().position(|x| !x.is_finite()) {
+            Some(i) => anyhow::bail!("{operation} failed: component {i} is {}", vector[i]),
+            None => Ok(()),
+        }
+    }
+
+    // a.add_item_normalized(i, v) is like a.add_item(i, v) but stores v scaled to unit length.
+    // Fails for a zero vector, which has no direction.
+    pub fn add_item_normalized(&mut self, item: u32, vector: &[f32]) -> anyhow::Result<()> {
+        self.add_item(item, &normalized(vector)?)
+    }
+
     // a.build(n_trees, n_jobs=-1) builds a forest of n_trees trees. More trees gives higher
     // precision when querying. After calling build, no more items can be added. n_jobs
     // specifies the number of threads used to build the trees. n_jobs=-1 uses all available
     // CPU cores.
+    //
+    // This crate compiles Annoy with its single-threaded build policy (see wrapper.cpp), so there's
+    // no n_jobs here: every build runs on the calling thread. Annoy doesn't use OpenMP either way,
+    // so OMP_NUM_THREADS has no effect. To build several indexes at once, call build from several
+    // threads, or bound the concurrency with build_in_pool.
     pub fn build(&mut self, n_trees: i32) -> anyhow::Result<()> {
+        debug_span!(
+            "annoy::build",
+            dimension = self.dimension,
+            n = self.get_n_items(),
+            n_trees
+        );
+        anyhow::ensure!(
+            self.get_n_items() > 0,
+            "build failed: cannot build an index with 0 items"
+        );
+        // Annoy casts n_trees to size_t, so any other negative count would build trees forever.
+        anyhow::ensure!(
+            n_trees >= -1,
+            "build failed: n_trees must be -1 or a number of trees, not {n_trees}"
+        );
         unsafe {
-            let mut error_ptr: *mut c_char = ptr::null_mut();
-            let success =
-                annoy_angular_build(self.ptr, n_trees as c_int, 1, &mut error_ptr as *mut _);
-            check_error("build", success, error_ptr)?;
+            let mut error = no_error();
+            let success = annoy_angular_build(self.ptr, n_trees as c_int, 1, &mut error);
+            check_error("build", success, error)?;
+        }
+        self.finish_build()
+    }
+
+    // a.build_within_memory(max_bytes) builds as many trees as estimated_build_memory says fit in
+    // max_bytes, and returns how many that was. Fails without building if not even one tree fits.
+    // The estimate is rough (see estimated_build_memory), so leave some headroom below a hard
+    // limit.
+    pub fn build_within_memory(&mut self, max_bytes: u64) -> anyhow::Result<u32> {
+        let n_items = self.get_n_items() as u64;
+        let n_trees = format::max_trees_within(n_items, self.dimension, max_bytes);
+        anyhow::ensure!(
+            n_trees > 0,
+            "build_within_memory failed: {n_items} items of dimension {} don't leave room for a tree in {max_bytes} bytes",
+            self.dimension
+        );
+        let n_trees = n_trees.min(i32::MAX as u64) as i32;
+        self.build(n_trees)?;
+        Ok(n_trees as u32)
+    }
+
+    // a.build_with_progress(n_trees, cb) is like a.build(n_trees) but calls cb with the number of
+    // trees built so far as each tree completes, e.g. to drive a progress bar. With n_trees = -1
+    // the total isn't known up front: trees are added until the forest has as many nodes as twice
+    // the number of items.
+    pub fn build_with_progress<F: FnMut(u32)>(
+        &mut self,
+        n_trees: i32,
+        cb: F,
+    ) -> anyhow::Result<()> {
+        debug_span!(
+            "annoy::build_with_progress",
+            dimension = self.dimension,
+            n = self.get_n_items(),
+            n_trees
+        );
+        anyhow::ensure!(
+            self.get_n_items() > 0,
+            "build failed: cannot build an index with 0 items"
+        );
+        // Annoy casts n_trees to size_t, so any other negative count would build trees forever.
+        anyhow::ensure!(
+            n_trees >= -1,
+            "build failed: n_trees must be -1 or a number of trees, not {n_trees}"
+        );
+
+        struct Progress<F> {
+            cb: F,
+            panic: Option<Box<dyn std::any::Any + Send>>,
+        }
+
+        // Unwinding out of the callback into C++ would be undefined behavior, so catch the panic,
+        // stop calling cb and resume it once the build has returned.
+        unsafe extern "C" fn trampoline<F: FnMut(u32)>(user_data: *mut c_void, n: c_int) {
+            let progress = &mut *(user_data as *mut Progress<F>);
+            if progress.panic.is_some() {
+                return;
+            }
+            let cb = &mut progress.cb;
+            if let Err(panic) =
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cb(n as u32)))
+            {
+                progress.panic = Some(panic);
+            }
+        }
+
+        let mut progress = Progress { cb, panic: None };
+        let result = unsafe {
+            let mut error = no_error();
+            let success = annoy_angular_build_with_progress(
+                self.ptr,
+                n_trees as c_int,
+                Some(trampoline::<F>),
+                &mut progress as *mut Progress<F> as *mut c_void,
+                &mut error,
+            );
+            check_error("build", success, error)
+        };
+        if let Some(panic) = progress.panic {
+            std::panic::resume_unwind(panic);
+        }
+        result?;
+        self.finish_build()
+    }
+
+    // a.build_in_pool(n_trees, pool) is like a.build(n_trees) but runs the build on one of pool's
+    // threads, blocking until it's done. Building many indexes this way from different threads
+    // runs at most pool.current_num_threads() builds at once, rather than oversubscribing the
+    // machine.
+    #[cfg(feature = "rayon")]
+    pub fn build_in_pool(&mut self, n_trees: i32, pool: &rayon::ThreadPool) -> anyhow::Result<()> {
+        pool.install(|| self.build(n_trees))
+    }
+
+    // a.unbuild() removes the trees so that more items can be added and the index built again.
+    // Loaded indexes can't be unbuilt.
+    pub fn unbuild(&mut self) -> anyhow::Result<()> {
+        unsafe {
+            let mut error = no_error();
+            let success = annoy_angular_unbuild(self.ptr, &mut error);
+            check_error("unbuild", success, error)?;
+        }
+        self.built = false;
+        Ok(())
+    }
+
+    // a.rebuild(n_trees) replaces the forest with one of n_trees trees over the same items, e.g. to
+    // tune the number of trees without re-adding every vector. It costs as much as the original
+    // build.
+    pub fn rebuild(&mut self, n_trees: i32) -> anyhow::Result<()> {
+        self.unbuild()?;
+        self.build(n_trees)
+    }
+
+    // Records that a build succeeded and publishes the file of an on_disk_build_with_scratch.
+    fn finish_build(&mut self) -> anyhow::Result<()> {
+        self.built = true;
+        if let Some((scratch_path, final_path)) = &self.publish {
+            self.flush()?;
+            fs::rename(scratch_path, final_path)?;
+            self.path = Some(final_path.clone());
+            self.publish = None;
         }
         Ok(())
     }
 
     // a.save(fn, prefault=False) saves the index to disk and loads it (see next function). After
     // saving, no more items can be added.
+    //
+    // Annoy's save silently does nothing for an index built with on_disk_build, whose file already
+    // holds it. So for such an index this writes a copy to fn with save_copy instead, and the index
+    // stays mapped from its on_disk_build file.
     pub fn save(&mut self, p: &Path) -> anyhow::Result<()> {
-        let p_str = p
-            .to_str()
-            .ok_or_else(|| anyhow::anyhow!("Path {p:?} is not valid UTF-8"))?;
-        let p_cstr = CString::new(p_str)?;
+        debug_span!("annoy::save", dimension = self.dimension, n = self.get_n_items(), path = ?p);
+        if self.is_on_disk() {
+            return self.save_copy(p);
+        }
+        let p_cstr = path_to_cstring(p)?;
         unsafe {
-            let mut error_ptr: *mut c_char = ptr::null_mut();
-            let success = annoy_angular_save(
-                self.ptr,
-                p_cstr.as_ptr() as *mut _,
-                false,
-                &mut error_ptr as *mut _,
-            );
-            check_error("save", success, error_ptr)?;
+            let mut error = no_error();
+            let success =
+                annoy_angular_save(self.ptr, p_cstr.as_ptr() as *mut _, false, &mut error);
+            check_error("save", success, error)?;
+        }
+        self.path = Some(p.to_owned());
+        Ok(())
+    }
+
+    // a.save_durable(fn) is like a.save(fn), but only returns once the index is on disk: it saves
+    // to a temporary file next to fn, syncs it, renames it into place and then syncs the directory
+    // so the rename itself survives a crash. A crash at any point leaves either the old fn or the
+    // complete new one, never a truncated file. The syncs wait for the disk, so this is slower
+    // than save, which leaves writing the file out to the OS.
+    //
+    // As with save, an index built with on_disk_build stays mapped from its own file, which is
+    // flushed, and fn gets a copy.
+    pub fn save_durable(&mut self, p: &Path) -> anyhow::Result<()> {
+        let tmp_path = tmp_path(p)?;
+        // A temporary file left by an earlier crash must never be what gets renamed to fn.
+        match fs::remove_file(&tmp_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        if self.is_on_disk() {
+            self.flush()?;
+            self.save_copy(p)?;
+            return sync_parent_dir(p);
+        }
+        self.save(&tmp_path)?;
+        File::open(&tmp_path)?.sync_all()?;
+        fs::rename(&tmp_path, p)?;
+        // The index stays mapped from the same file under its new name.
+        self.path = Some(p.to_owned());
+        sync_parent_dir(p)
+    }
+
+    // a.save_copy(fn) writes a copy of the built index to fn, without changing which file (if any)
+    // a is backed by; save uses it for an index built with on_disk_build. The copy is written to a
+    // temporary file next to fn, flushed to disk and then renamed into place, so other processes
+    // never observe a partially written fn.
+    pub fn save_copy(&self, p: &Path) -> anyhow::Result<()> {
+        let nodes = self
+            .nodes()
+            .ok_or_else(|| anyhow::anyhow!("save_copy failed: the index hasn't been built"))?;
+        let tmp_path = tmp_path(p)?;
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(nodes)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, p)?;
+        Ok(())
+    }
+
+    // a.export_vectors(data_fn, ids_fn) writes every item's vector to data_fn as a row-major array
+    // of f32s, and the items' ids to ids_fn as u32s in the same order, both in native byte order.
+    // Ids that were never added are skipped, so the id list is what maps rows back to items.
+    // data_fn can be read straight back with a.add_items_from_slice when the ids are 0..n.
+    pub fn export_vectors(&self, data_path: &Path, ids_path: &Path) -> anyhow::Result<()> {
+        let mut data = BufWriter::new(File::create(data_path)?);
+        let mut ids = BufWriter::new(File::create(ids_path)?);
+        for item in 0..self.get_n_items() {
+            let Some(vector) = self.item_vector_ref(item) else {
+                continue;
+            };
+            for x in vector {
+                data.write_all(&x.to_ne_bytes())?;
+            }
+            ids.write_all(&item.to_ne_bytes())?;
         }
+        data.flush()?;
+        ids.flush()?;
         Ok(())
     }
 
     // a.load(fn, prefault=False) loads (mmaps) an index from disk. If prefault is set to True, it
     // will pre-read the entire file into memory (using mmap with MAP_POPULATE). Default is False.
+    //
+    // Fails with AnnoyError::DimensionMismatch if the file's size shows that it was saved with a
+    // different dimension.
+    //
+    // Files saved by Annoy's Python bindings (or any other build of the same C++ core) load as-is:
+    // the file is just the node array, without a header or format version that could differ.
+    // That also means there's no version to check after upgrading this crate: the angular node
+    // layout (n_descendants, two children, then the vector) hasn't changed across Annoy releases,
+    // so every file saved with this crate or with upstream Annoy's int32_t ids and float vectors
+    // loads. If a future Annoy did change the layout, old files would most likely fail the size
+    // check here or try_load's check of the roots rather than be detected by version.
     pub fn load(&mut self, p: &Path) -> anyhow::Result<()> {
-        let p_str = p
-            .to_str()
-            .ok_or_else(|| anyhow::anyhow!("Path {p:?} is not valid UTF-8"))?;
-        let p_cstr = CString::new(p_str)?;
+        debug_span!("annoy::load", dimension = self.dimension, path = ?p);
+        format::check_dimension(p, self.dimension)?;
+        let p_cstr = path_to_cstring(p)?;
         unsafe {
-            let mut error_ptr: *mut c_char = ptr::null_mut();
-            let success = annoy_angular_load(
-                self.ptr,
-                p_cstr.as_ptr() as *mut _,
-                false,
-                &mut error_ptr as *mut _,
-            );
-            check_error("load", success, error_ptr)?;
+            let mut error = no_error();
+            let success =
+                annoy_angular_load(self.ptr, p_cstr.as_ptr() as *mut _, false, &mut error);
+            check_error("load", success, error)?;
+        }
+        self.built = true;
+        self.path = Some(p.to_owned());
+        Ok(())
+    }
+
+    // AnnoyAngular::try_load(fn, f) returns a new index of f-dimensional vectors with the index at
+    // fn loaded, like AnnoyAngular::new(f) followed by a.load(fn), after checking that fn is a
+    // whole index. A file that was truncated (e.g. by a crash during save) or isn't an index at all
+    // is an AnnoyError::CorruptIndex, rather than being mapped and returning garbage from queries.
+    //
+    // Annoy files have no header or checksum, so the check looks at the file's size and its roots:
+    // damage in the middle of the file, e.g. to an item's vector, isn't detected. There's no way to
+    // repair a corrupt index either; rebuild it from the source vectors.
+    pub fn try_load(p: &Path, dimension: usize) -> anyhow::Result<Self> {
+        format::check_index(p, dimension)?;
+        let mut index = Self::try_new(dimension)?;
+        index.load(p)?;
+        Ok(index)
+    }
+
+    // a.load_with_advice(fn, advice) is like a.load(fn) but then tells the kernel how the mapped
+    // file will be read. This is a no-op on platforms without madvise.
+    pub fn load_with_advice(&mut self, p: &Path, advice: MmapAdvice) -> anyhow::Result<()> {
+        self.load(p)?;
+        #[cfg(unix)]
+        {
+            let advice = match advice {
+                MmapAdvice::Normal => libc::MADV_NORMAL,
+                MmapAdvice::Random => libc::MADV_RANDOM,
+                MmapAdvice::Sequential => libc::MADV_SEQUENTIAL,
+                MmapAdvice::WillNeed => libc::MADV_WILLNEED,
+            };
+            if let Some(nodes) = self.nodes() {
+                // The nodes start at the beginning of the mapping, so they're page aligned.
+                let rc = unsafe { libc::madvise(nodes.as_ptr() as *mut _, nodes.len(), advice) };
+                if rc != 0 {
+                    anyhow::bail!("madvise failed: {}", std::io::Error::last_os_error());
+                }
+            }
         }
+        #[cfg(not(unix))]
+        let _ = advice;
         Ok(())
     }
 
-    // a.unload() unloads.
+    // a.warm() reads a byte from every page of the index so that the first queries after a.load
+    // don't stall on page faults, like loading with prefault but at a time of the caller's
+    // choosing, e.g. off the serving path. Pages the kernel evicts later will fault in again. Does
+    // nothing for an index that hasn't been built or loaded.
+    pub fn warm(&self) {
+        const PAGE_SIZE: usize = 4096;
+        if let Some(nodes) = self.nodes() {
+            for offset in (0..nodes.len()).step_by(PAGE_SIZE) {
+                // A volatile read, so the compiler can't skip loads whose values go unused.
+                unsafe { ptr::read_volatile(nodes.as_ptr().add(offset)) };
+            }
+        }
+    }
+
+    // AnnoyAngular::reopen_for_append(fn, f) loads the index saved at fn and copies all of its
+    // items into a fresh, unbuilt index so that more items can be added before building again.
+    // Annoy can't add items to a built index, so this re-inserts every vector and the next build
+    // rebuilds the whole forest: expect it to cost as much as building from scratch, on top of
+    // holding a full in-RAM copy of the vectors. Ids that were never added stay unused.
+    pub fn reopen_for_append(p: &Path, dimension: usize) -> anyhow::Result<Self> {
+        let mut loaded = Self::try_new(dimension)?;
+        loaded.load(p)?;
+        let mut index = Self::try_new(dimension)?;
+        for item in 0..loaded.get_n_items() {
+            if let Some(vector) = loaded.item_vector_ref(item) {
+                index.add_item(item, vector)?;
+            }
+        }
+        Ok(index)
+    }
+
+    // AnnoyAngular::merge(a, b, id_offset) returns a new index holding the items of both a and b.
+    // Annoy can't combine forests, so this copies every item into a fresh index and builds it with
+    // as many trees as the larger of the two forests, or Annoy's default if neither has been
+    // built; call rebuild afterwards for another count.
+    //
+    // Items from a keep their ids and items from b are shifted up by id_offset, so item i of b is
+    // item i + id_offset of the result. id_offset must be at least a.get_n_items(), so that the two
+    // sets of ids can't collide. Ids that were never added to a or b stay unused.
+    pub fn merge(a: &AnnoyAngular, b: &AnnoyAngular, id_offset: u32) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            a.dimension == b.dimension,
+            "merge failed: can't merge indexes of dimension {} and {}",
+            a.dimension,
+            b.dimension
+        );
+        let a_items = a.get_n_items();
+        anyhow::ensure!(
+            id_offset >= a_items,
+            "merge failed: id_offset {id_offset} would collide with the {a_items} ids of the first index"
+        );
+        let b_items = b.get_n_items();
+        let n_items = id_offset.checked_add(b_items).ok_or_else(|| {
+            anyhow::anyhow!(
+                "merge failed: id_offset {id_offset} overflows the ids of the second index"
+            )
+        })?;
+        let mut index = Self::try_new(a.dimension)?;
+        index.reserve(n_items)?;
+        for item in 0..a_items {
+            if let Some(vector) = a.item_vector_ref(item) {
+                index.add_item(item, vector)?;
+            }
+        }
+        for item in 0..b_items {
+            if let Some(vector) = b.item_vector_ref(item) {
+                index.add_item(item + id_offset, vector)?;
+            }
+        }
+        // With no trees in either input, build(0) would leave an index no query finds anything in.
+        let n_trees = match a.get_n_trees().max(b.get_n_trees()) {
+            0 => -1,
+            n_trees => n_trees as i32,
+        };
+        index.build(n_trees)?;
+        Ok(index)
+    }
+
+    // a.retain(keep, n_trees) returns a new index built with n_trees trees (-1 for Annoy's default)
+    // from the items of a for which keep(i) is true, e.g. to drop deleted items, which Annoy can't
+    // remove in place. a itself is unchanged.
+    //
+    // Kept items keep their ids, so the results of queries on the new index need no mapping back.
+    // The ids that were dropped become holes, which still take up a node each below the highest
+    // kept id. To compact the ids instead, push the kept vectors into a new index and keep a table
+    // from new ids to old ones.
+    pub fn retain<F: Fn(u32) -> bool>(&self, keep: F, n_trees: i32) -> anyhow::Result<Self> {
+        let mut index = Self::try_new(self.dimension)?;
+        for item in 0..self.get_n_items() {
+            if let Some(vector) = self.item_vector_ref(item) {
+                if keep(item) {
+                    index.add_item(item, vector)?;
+                }
+            }
+        }
+        index.build(n_trees)?;
+        Ok(index)
+    }
+
+    // a.reset() frees the underlying index and replaces it with a fresh, empty one of the same
+    // dimension. Afterwards the object is in the same state as right after `new`.
+    pub fn reset(&mut self) {
+        unsafe {
+            if !self.ptr.is_null() {
+                annoy_angular_free_index(self.ptr);
+            }
+            self.ptr = create_index(self.dimension);
+        }
+        self.built = false;
+        self.publish = None;
+        self.capacity = None;
+        self.path = None;
+        self.check_finite = false;
+    }
+
+    // a.unload() unloads. Afterwards the index is empty, and queries fail until it's built or
+    // loaded again.
     pub fn unload(&mut self) {
         unsafe {
             annoy_angular_unload(self.ptr);
         }
+        self.built = false;
+        self.publish = None;
+        self.path = None;
+    }
+
+    // a.is_built() returns whether the index has been built or loaded, i.e. whether it can be
+    // queried. Items can only be added while it returns false.
+    pub fn is_built(&self) -> bool {
+        self.built
+    }
+
+    // a.current_path() returns the file the index is mapped from: the one it was last saved to or
+    // loaded from, or the one on_disk_build is writing (for on_disk_build_with_scratch, the
+    // scratch file until build moves it into place). None for an index in RAM, including after
+    // unload or reset.
+    pub fn current_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    // a.backing() returns whether the index's nodes are in RAM or mapped from a file.
+    pub fn backing(&self) -> Backing {
+        if unsafe { annoy_angular_is_mapped(self.ptr) } {
+            Backing::Mmap
+        } else {
+            Backing::Ram
+        }
     }
 
     // a.get_nns_by_item(i, n, search_k=-1, include_distances=False) returns the n closest items.
@@ -127,57 +911,433 @@ impl AnnoyAngular {
     // provided. search_k gives you a run-time tradeoff between better accuracy and speed. If you
     // set include_distances to True, it will return a 2 element tuple with two lists in it: the
     // second one containing all corresponding distances.
+    //
+    // Querying an item id that's past a.get_n_items() is an error. Annoy allocates max(i)+1 items,
+    // though, so ids below that which were never added ("holes") aren't detected and querying one
+    // returns meaningless neighbors.
+    //
+    // There's no extra lookup to cache here: Annoy reads the query vector straight out of the
+    // item's node, which is the same (mmapped) page a.get_item_vector(i) would read, and then runs
+    // exactly the search a.get_nns_by_vector would. Fanning out many queries anchored on one item
+    // with a.get_nns_by_item therefore costs the same as fetching its vector once and calling
+    // a.get_nns_by_vector repeatedly.
     pub fn get_nearest_by_item(
-        &mut self,
+        &self,
         item: u32,
         n: usize,
         search_k: i32,
     ) -> anyhow::Result<(Vec<u32>, Vec<f32>)> {
-        // TODO: bounds checking?
+        debug_span!(
+            "annoy::get_nearest_by_item",
+            dimension = self.dimension,
+            n,
+            search_k
+        );
+        self.ensure_built("get_nns_by_item")?;
+        let n_items = self.get_n_items();
+        anyhow::ensure!(
+            item < n_items,
+            "Item {item} is out of bounds for an index with {n_items} items"
+        );
+        // Annoy never returns more than one result per item, so don't size the buffers for an `n`
+        // that's larger than the index.
+        let capacity = n.min(n_items as usize);
         unsafe {
-            let mut results = Vec::with_capacity(n);
-            let mut distances = Vec::with_capacity(n);
-            let num_results = annoy_angular_get_nns_by_item(
+            let mut results = Vec::with_capacity(capacity);
+            let mut distances = Vec::with_capacity(capacity);
+            let mut num_results = 0;
+            let mut error = no_error();
+            let success = annoy_angular_get_nns_by_item(
                 self.ptr,
                 item,
                 n,
                 search_k,
                 results.as_mut_ptr(),
                 distances.as_mut_ptr(),
+                &mut num_results,
+                &mut error,
             );
+            check_error("get_nns_by_item", success, error)?;
             results.set_len(num_results);
             distances.set_len(num_results);
+            debug_assert_sorted(&distances);
             Ok((results, distances))
         }
     }
 
+    // a.all_nearest(n, search_k) iterates over every item in id order along with its n nearest
+    // neighbors, as a.get_nearest_by_item(i, n, search_k) finds them, e.g. to build a kNN graph.
+    // An item is normally its own nearest neighbor, so ask for n + 1 to get n others. Ids that
+    // were never added are skipped. With the rayon feature, ReadOnlyAnnoy::par_all_nearest does
+    // the same queries in parallel.
+    //
+    // Each item's query can fail on its own, so the iterator yields Results: collect into an
+    // anyhow::Result<Vec<_>> to stop at the first error.
+    pub fn all_nearest(
+        &self,
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(u32, Vec<Neighbor>)>> + '_> {
+        // Fail before iterating for an index that can't be queried at all.
+        self.ensure_built("all_nearest")?;
+        Ok((0..self.get_n_items())
+            .filter(move |&item| self.item_vector_ref(item).is_some())
+            .map(move |item| {
+                let (results, distances) = self.get_nearest_by_item(item, n, search_k)?;
+                Ok((item, to_neighbors(results, distances, DistanceUnit::Annoy)))
+            }))
+    }
+
+    // a.get_nearest_by_items(items, n, search_k) returns the n nearest neighbors of the centroid of
+    // items ("more like these"): their vectors are each scaled to unit length, since only
+    // direction matters for the angular metric, averaged, and the result queried by vector. The
+    // items themselves are usually among the results. It's an error if items is empty, if one of
+    // them was never added, or if their directions cancel out to a zero vector.
+    pub fn get_nearest_by_items(
+        &self,
+        items: &[u32],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<(Vec<u32>, Vec<f32>)> {
+        anyhow::ensure!(!items.is_empty(), "get_nearest_by_items failed: no items");
+        let mut centroid = vec![0.0; self.dimension];
+        for &item in items {
+            let vector = self.item_vector_ref(item).ok_or_else(|| {
+                anyhow::anyhow!("get_nearest_by_items failed: item {item} was never added")
+            })?;
+            for (c, x) in centroid.iter_mut().zip(normalized(vector)?) {
+                *c += x;
+            }
+        }
+        normalize(&mut centroid)?;
+        self.get_nearest_by_vector(centroid, n, search_k)
+    }
+
     // a.get_nns_by_vector(v, n, search_k=-1, include_distances=False) same but query by vector v.
+    //
+    // v can be anything that derefs to a slice, including an owned Vec<f32>, which is handy when
+    // the query vector is owned by a future.
     pub fn get_nearest_by_vector(
-        &mut self,
+        &self,
+        vector: impl AsRef<[f32]>,
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<(Vec<u32>, Vec<f32>)> {
+        let vector = vector.as_ref();
+        debug_span!(
+            "annoy::get_nearest_by_vector",
+            dimension = self.dimension,
+            n,
+            search_k
+        );
+        let mut results = Vec::new();
+        let mut distances = Vec::new();
+        self.get_nearest_by_vector_into(vector, n, search_k, &mut results, &mut distances)?;
+        Ok((results, distances))
+    }
+
+    // a.get_nearest_by_vector_into(v, n, search_k, results, distances) is get_nearest_by_vector
+    // writing into caller-provided buffers, which are cleared first. Reusing the same buffers
+    // across queries avoids allocating result Vecs once they've grown to fit n, e.g. when they
+    // come from a per-thread or arena-backed pool.
+    pub fn get_nearest_by_vector_into(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+        results: &mut Vec<u32>,
+        distances: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(vector.len() == self.dimension);
+        self.check_finite("get_nearest_by_vector", vector)?;
+        unsafe {
+            self.get_nearest_by_vector_into_unchecked(vector, n, search_k, results, distances)
+        }
+    }
+
+    /// a.get_nearest_by_vector_unchecked(v, n, search_k) is a.get_nearest_by_vector(v, n, search_k)
+    /// without checking that v has a.dimension components, for hot loops whose query vectors have
+    /// already been checked.
+    ///
+    /// # Safety
+    ///
+    /// As for add_item_unchecked, v must have at least the index's dimension components, or the
+    /// search reads past the end of it.
+    pub unsafe fn get_nearest_by_vector_unchecked(
+        &self,
         vector: &[f32],
         n: usize,
         search_k: i32,
     ) -> anyhow::Result<(Vec<u32>, Vec<f32>)> {
+        let mut results = Vec::new();
+        let mut distances = Vec::new();
+        unsafe {
+            self.get_nearest_by_vector_into_unchecked(
+                vector,
+                n,
+                search_k,
+                &mut results,
+                &mut distances,
+            )?;
+        }
+        Ok((results, distances))
+    }
+
+    // The part of get_nearest_by_vector_into after checking v's length. Callers must make sure v
+    // has at least self.dimension components.
+    unsafe fn get_nearest_by_vector_into_unchecked(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+        results: &mut Vec<u32>,
+        distances: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        unsafe { self.query_by_vector(vector, n, search_k, results, Some(distances)) }
+    }
+
+    // a.get_nearest_ids_by_vector(v, n, search_k) is get_nearest_by_vector without the distances.
+    // Annoy still computes the distances to rank the candidates, but skips converting them for the
+    // results.
+    pub fn get_nearest_ids_by_vector(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<Vec<u32>> {
+        anyhow::ensure!(vector.len() == self.dimension);
+        self.check_finite("get_nearest_ids_by_vector", vector)?;
+        let mut results = Vec::new();
+        unsafe { self.query_by_vector(vector, n, search_k, &mut results, None)? };
+        Ok(results)
+    }
+
+    // Runs the get_nns_by_vector shim, passing it a null distances array if distances is None.
+    // Callers must make sure v has at least self.dimension components.
+    unsafe fn query_by_vector(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+        results: &mut Vec<u32>,
+        mut distances: Option<&mut Vec<f32>>,
+    ) -> anyhow::Result<()> {
+        self.ensure_built("get_nns_by_vector")?;
+        // Annoy never returns more than one result per item, so don't size the buffers for an `n`
+        // that's larger than the index.
+        let capacity = n.min(self.get_n_items() as usize);
+        results.clear();
+        results.reserve(capacity);
+        if let Some(distances) = distances.as_deref_mut() {
+            distances.clear();
+            distances.reserve(capacity);
+        }
+        unsafe {
+            let mut num_results = 0;
+            let mut error = no_error();
+            let success = annoy_angular_get_nns_by_vector(
+                self.ptr,
+                vector.as_ptr() as *mut _,
+                n,
+                search_k,
+                results.as_mut_ptr(),
+                distances
+                    .as_deref_mut()
+                    .map_or(ptr::null_mut(), |distances| distances.as_mut_ptr()),
+                &mut num_results,
+                &mut error,
+            );
+            check_error("get_nns_by_vector", success, error)?;
+            results.set_len(num_results);
+            if let Some(distances) = distances {
+                distances.set_len(num_results);
+                debug_assert_sorted(distances);
+            }
+        }
+        Ok(())
+    }
+
+    // a.nearest(v, search_k) returns the closest item to v, or None if the search found nothing.
+    pub fn nearest(&self, vector: &[f32], search_k: i32) -> anyhow::Result<Option<Neighbor>> {
+        let (results, distances) = self.get_nearest_by_vector(vector, 1, search_k)?;
+        Ok(results
+            .first()
+            .zip(distances.first())
+            .map(|(&id, &distance)| Neighbor { id, distance }))
+    }
+
+    // a.query_with_stats(v, n, search_k) is like get_nearest_by_vector but also reports how much
+    // work the query did, which helps to pick search_k. The search is a copy of Annoy's in
+    // wrapper.cpp, so it finds the same neighbors.
+    pub fn query_with_stats(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<(Vec<Neighbor>, QueryStats)> {
+        debug_span!(
+            "annoy::query_with_stats",
+            dimension = self.dimension,
+            n,
+            search_k
+        );
+        self.ensure_built("get_nns_by_vector_with_stats")?;
         anyhow::ensure!(vector.len() == self.dimension);
+        let capacity = n.min(self.get_n_items() as usize);
+        let mut results = Vec::with_capacity(capacity);
+        let mut distances = Vec::with_capacity(capacity);
+        let mut stats = QueryStats::default();
         unsafe {
-            let mut results = Vec::with_capacity(n);
-            let mut distances = Vec::with_capacity(n);
-            let num_results = annoy_angular_get_nns_by_vector(
+            let mut num_results = 0;
+            let mut error = no_error();
+            let success = annoy_angular_get_nns_by_vector_with_stats(
                 self.ptr,
                 vector.as_ptr() as *mut _,
                 n,
                 search_k,
                 results.as_mut_ptr(),
                 distances.as_mut_ptr(),
+                &mut num_results,
+                &mut stats.nodes_visited,
+                &mut stats.trees_used,
+                &mut stats.n_candidates,
+                &mut error,
             );
+            check_error("get_nns_by_vector_with_stats", success, error)?;
             results.set_len(num_results);
             distances.set_len(num_results);
-            Ok((results, distances))
         }
+        debug_assert_sorted(&distances);
+        let neighbors = results
+            .into_iter()
+            .zip(distances)
+            .map(|(id, distance)| Neighbor { id, distance })
+            .collect();
+        Ok((neighbors, stats))
+    }
+
+    // a.get_within_distance(v, max_distance, search_k) returns every item within max_distance of v
+    // that the search finds, closest first. Rather than having the caller guess an n that's large
+    // enough, it queries with a small n and doubles it until the farthest result is past
+    // max_distance (results come back sorted, so nothing closer was cut off) or the search runs
+    // out of items. Like any Annoy query this is approximate: a larger search_k finds more of the
+    // items that are really within range.
+    //
+    // Each round is a whole new query rather than the next batch of a.iter_nearest_by_vector:
+    // with search_k = -1 a larger n searches more of the forest, so a later batch can turn up an
+    // item closer than the first one past max_distance, which stopping at that one would miss.
+    pub fn get_within_distance(
+        &self,
+        vector: &[f32],
+        max_distance: f32,
+        search_k: i32,
+    ) -> anyhow::Result<Vec<Neighbor>> {
+        let n_items = self.get_n_items() as usize;
+        let mut n = n_items.clamp(1, 16);
+        loop {
+            let (results, distances) = self.get_nearest_by_vector(vector, n, search_k)?;
+            let exhausted = results.len() < n || n >= n_items;
+            let past_bound = distances.last().is_none_or(|&d| d > max_distance);
+            if exhausted || past_bound {
+                return Ok(results
+                    .into_iter()
+                    .zip(distances)
+                    .take_while(|&(_, distance)| distance <= max_distance)
+                    .map(|(id, distance)| Neighbor { id, distance })
+                    .collect());
+            }
+            n = (n * 2).min(n_items);
+        }
+    }
+
+    // a.count_within_distance(v, max_distance, search_k) is the number of items
+    // a.get_within_distance(v, max_distance, search_k) would return. It's defined as exactly that,
+    // so the two always agree.
+    pub fn count_within_distance(
+        &self,
+        vector: &[f32],
+        max_distance: f32,
+        search_k: i32,
+    ) -> anyhow::Result<usize> {
+        Ok(self
+            .get_within_distance(vector, max_distance, search_k)?
+            .len())
+    }
+
+    // a.get_nearest_by_vector_map(v, n, search_k, f) is like get_nearest_by_vector but applies f to
+    // each distance, returning (item, f(distance)) pairs in the same closest-first order.
+    pub fn get_nearest_by_vector_map<F: Fn(f32) -> f32>(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+        f: F,
+    ) -> anyhow::Result<Vec<(u32, f32)>> {
+        let (results, distances) = self.get_nearest_by_vector(vector, n, search_k)?;
+        Ok(results
+            .into_iter()
+            .zip(distances)
+            .map(|(r, d)| (r, f(d)))
+            .collect())
+    }
+
+    // a.get_nearest_by_vector_map_ids(v, n, search_k) is get_nearest_by_vector with the results
+    // collected into a map from item to distance, for callers that look neighbors up by id rather
+    // than walking them in order.
+    pub fn get_nearest_by_vector_map_ids(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<HashMap<u32, f32>> {
+        let (results, distances) = self.get_nearest_by_vector(vector, n, search_k)?;
+        Ok(results.into_iter().zip(distances).collect())
+    }
+
+    // a.get_nearest_by_vector_in(v, n, search_k, unit) is get_nearest_by_vector with the distances
+    // converted to the given unit, e.g. DistanceUnit::CosineDistance for 1 - cos.
+    pub fn get_nearest_by_vector_in(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+        unit: DistanceUnit,
+    ) -> anyhow::Result<Vec<Neighbor>> {
+        let (results, distances) = self.get_nearest_by_vector(vector, n, search_k)?;
+        Ok(to_neighbors(results, distances, unit))
+    }
+
+    // a.get_nearest_by_item_in(i, n, search_k, unit) is get_nearest_by_item with the distances
+    // converted to the given unit.
+    pub fn get_nearest_by_item_in(
+        &self,
+        item: u32,
+        n: usize,
+        search_k: i32,
+        unit: DistanceUnit,
+    ) -> anyhow::Result<Vec<Neighbor>> {
+        let (results, distances) = self.get_nearest_by_item(item, n, search_k)?;
+        Ok(to_neighbors(results, distances, unit))
+    }
+
+    // a.get_nearest_by_vector_f64(v, n, search_k) is get_nearest_by_vector with the distances
+    // widened to f64 for callers that accumulate them. Annoy stores vectors and computes distances
+    // in f32, so this doesn't make the distances themselves any more precise.
+    pub fn get_nearest_by_vector_f64(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<(Vec<u32>, Vec<f64>)> {
+        let (results, distances) = self.get_nearest_by_vector(vector, n, search_k)?;
+        Ok((results, distances.into_iter().map(f64::from).collect()))
     }
 
     // a.get_item_vector(i) returns the vector for item i that was previously added.
-    pub fn get_item_vector(&mut self, item: u32) -> Vec<f32> {
+    //
+    // Panics if i is past a.get_n_items(), e.g. after unload.
+    pub fn get_item_vector(&self, item: u32) -> Vec<f32> {
+        self.assert_in_bounds(item);
         let mut vector = Vec::with_capacity(self.dimension);
         unsafe {
             annoy_angular_get_item(self.ptr, item, vector.as_mut_ptr());
@@ -186,54 +1346,350 @@ impl AnnoyAngular {
         vector
     }
 
-    // a.get_distance(i, j) returns the distance between items i and j.
-    pub fn get_distance(&mut self, i: u32, j: u32) -> f32 {
-        unsafe { annoy_angular_get_distance(self.ptr, i, j) }
+    // a.item_vector_ref(i) is like a.get_item_vector(i) but borrows the vector from the index's own
+    // storage (the mapped file, for a loaded index) instead of copying it. Returns None if item i
+    // was never added.
+    pub fn item_vector_ref(&self, item: u32) -> Option<&[f32]> {
+        let vector = unsafe { annoy_angular_get_item_ptr(self.ptr, item) };
+        if vector.is_null() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts(vector, self.dimension) })
     }
 
-    // a.get_n_items() returns the number of items in the index.
-    pub fn get_n_items(&mut self) -> u32 {
-        unsafe { annoy_angular_get_n_items(self.ptr) }
+    // a.iter_items() iterates over (i, a.get_item_vector(i)) for every item i that was added, in id
+    // order. Ids below a.get_n_items() that were never added are skipped rather than returned as
+    // zero vectors, so the items can be re-added elsewhere as they are.
+    pub fn iter_items(&self) -> impl Iterator<Item = (u32, Vec<f32>)> + '_ {
+        (0..self.get_n_items())
+            .filter_map(move |item| Some((item, self.item_vector_ref(item)?.to_vec())))
+    }
+
+    // a.get_distance(i, j) returns the distance between items i and j.
+    //
+    // Panics if i or j is past a.get_n_items().
+    pub fn get_distance(&self, i: u32, j: u32) -> AngularDistance {
+        self.assert_in_bounds(i);
+        self.assert_in_bounds(j);
+        AngularDistance(unsafe { annoy_angular_get_distance(self.ptr, i, j) })
+    }
+
+    // a.distance_matrix(ids) returns the distances between every pair of the given items, as rows:
+    // entry [k][l] is a.get_distance(ids[k], ids[l]). The diagonal is 0 and the matrix is
+    // symmetric, so each pair is only computed once. Unlike get_distance, an id that's out of
+    // bounds or was never added is an error.
+    pub fn distance_matrix(&self, ids: &[u32]) -> anyhow::Result<Vec<Vec<f32>>> {
+        for &id in ids {
+            anyhow::ensure!(
+                self.item_vector_ref(id).is_some(),
+                "distance_matrix failed: item {id} was never added"
+            );
+        }
+        let mut matrix = vec![vec![0.0; ids.len()]; ids.len()];
+        for k in 0..ids.len() {
+            for l in k + 1..ids.len() {
+                let distance = self.get_distance(ids[k], ids[l]).0;
+                matrix[k][l] = distance;
+                matrix[l][k] = distance;
+            }
+        }
+        Ok(matrix)
+    }
+
+    // a.get_n_items() returns the number of items in the index.
+    //
+    // The index is instantiated with int32_t item ids (see wrapper.cpp), so the count is at most
+    // i32::MAX and always fits in a u32 without truncation.
+    pub fn get_n_items(&self) -> u32 {
+        unsafe { annoy_angular_get_n_items(self.ptr) }
+    }
+
+    // a.get_n_trees() returns the number of trees in the index.
+    pub fn get_n_trees(&self) -> u32 {
+        unsafe { annoy_angular_get_n_trees(self.ptr) as u32 }
+    }
+
+    // a.get_n_nodes() returns the number of nodes in the index: one per item (including ids that
+    // were never added), plus the split and leaf nodes of every tree and a copy of each root that
+    // Annoy appends at the end. Each node takes 12 + 4 * f bytes. Before build there are only the
+    // item nodes.
+    pub fn get_n_nodes(&self) -> u64 {
+        match self.nodes() {
+            Some(nodes) => nodes.len() as u64 / format::node_size(self.dimension),
+            None => self.get_n_items() as u64,
+        }
+    }
+
+    // a.max_leaf_size() returns the most items a leaf node can hold, Annoy's _K: a tree stops
+    // splitting once a node has at most this many items. Bigger leaves mean shallower trees that
+    // check more candidates per leaf. It isn't tunable: Annoy stores a leaf's item ids in the
+    // space an item node uses for its vector, so _K is fixed by the dimension (f + 2 for the
+    // angular metric), and changing it would change the file format. Use n_trees and search_k to
+    // trade accuracy for speed instead.
+    pub fn max_leaf_size(&self) -> usize {
+        format::max_leaf_size(self.dimension) as usize
+    }
+
+    // a.verify() sanity checks a built or loaded index before it's used to serve queries: it must
+    // have items and trees, and a probe query for item 0's nearest neighbor must return exactly
+    // one result. This catches truncated or garbage files that happen to load, but it doesn't
+    // inspect every node, so it's no substitute for a checksum.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.built,
+            "verify failed: the index hasn't been built or loaded"
+        );
+        let n_items = self.get_n_items();
+        anyhow::ensure!(n_items > 0, "verify failed: the index has no items");
+        let n_trees = self.get_n_trees();
+        anyhow::ensure!(n_trees > 0, "verify failed: the index has no trees");
+        let (results, _) = self.get_nearest_by_item(0, 1, -1)?;
+        anyhow::ensure!(
+            results.len() == 1,
+            "verify failed: probe query returned {} results instead of 1",
+            results.len()
+        );
+        anyhow::ensure!(
+            results[0] < n_items,
+            "verify failed: probe query returned item {} but the index only has {n_items} items",
+            results[0]
+        );
+        Ok(())
     }
 
     // a.on_disk_build(fn) prepares annoy to build the index in the specified file instead
     // of RAM (execute before adding items, no need to save after build)
+    //
+    // Items added afterwards are written straight into the file through a shared mapping, as are
+    // the trees during build, so the index never has to fit in RAM: the kernel can write pages
+    // back to the file and evict them under memory pressure.
+    //
+    // The index can be queried as soon as build returns, through the same mapping: there's no need
+    // to save or load the file again.
     pub fn on_disk_build(&mut self, p: &Path) -> anyhow::Result<()> {
-        let p_str = p
-            .to_str()
-            .ok_or_else(|| anyhow::anyhow!("Path {p:?} is not valid UTF-8"))?;
-        let p_cstr = CString::new(p_str)?;
+        let p_cstr = path_to_cstring(p)?;
         unsafe {
-            let mut error_ptr: *mut c_char = ptr::null_mut();
-            let success = annoy_angular_on_disk_build(
-                self.ptr,
-                p_cstr.as_ptr() as *mut _,
-                &mut error_ptr as *mut _,
+            let mut error = no_error();
+            let success =
+                annoy_angular_on_disk_build(self.ptr, p_cstr.as_ptr() as *mut _, &mut error);
+            check_error("on_disk_build", success, error)?;
+        }
+        self.path = Some(p.to_owned());
+        Ok(())
+    }
+
+    // a.on_disk_build_with_scratch(fn, scratch_dir) is like a.on_disk_build(fn), except that the
+    // index is built in a file in scratch_dir and only moved to fn once build has succeeded and
+    // flushed it to disk. Readers of fn therefore never see a partially built index. The move is
+    // a rename, so scratch_dir must be on the same filesystem as fn (this is checked on Unix).
+    pub fn on_disk_build_with_scratch(
+        &mut self,
+        p: &Path,
+        scratch_dir: &Path,
+    ) -> anyhow::Result<()> {
+        let file_name = p
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Path {p:?} has no file name"))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let parent = match p.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            anyhow::ensure!(
+                fs::metadata(scratch_dir)?.dev() == fs::metadata(parent)?.dev(),
+                "on_disk_build_with_scratch failed: {scratch_dir:?} and {parent:?} are on different filesystems"
             );
-            check_error("on_disk_build", success, error_ptr)?;
         }
+        let scratch_path = scratch_dir.join(file_name);
+        self.on_disk_build(&scratch_path)?;
+        self.publish = Some((scratch_path, p.to_owned()));
+        Ok(())
+    }
+
+    // a.flush() makes sure an index built with on_disk_build has reached the disk. Once build
+    // returns, the whole index is already visible to any process that opens the file afterwards,
+    // since they share the same page cache; a reader that opens it earlier can see a partially
+    // built file. flush additionally waits for the file to be written out, so that it survives a
+    // crash. It's a no-op for in-RAM and loaded indexes.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        unsafe {
+            let mut error = no_error();
+            let success = annoy_angular_flush(self.ptr, &mut error);
+            check_error("flush", success, error)?;
+        }
+        Ok(())
+    }
+
+    // a.stats() returns the number of items and trees, the dimension, the metric and the size of
+    // the index in one struct. It works on both in-RAM and loaded indexes.
+    pub fn stats(&self) -> anyhow::Result<IndexStats> {
+        let n_items = self.get_n_items();
+        let bytes = match self.nodes() {
+            Some(nodes) => nodes.len() as u64,
+            None => n_items as u64 * format::node_size(self.dimension),
+        };
+        Ok(IndexStats {
+            n_items,
+            n_trees: self.get_n_trees(),
+            dimension: self.dimension,
+            metric: "angular",
+            bytes,
+        })
+    }
+
+    // Queries need trees, so fail with a clearer message than Annoy's before an index is built,
+    // or once it has been unloaded or reset.
+    fn ensure_built(&self, operation: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.built,
+            "{operation} failed: the index isn't built or loaded"
+        );
         Ok(())
     }
+
+    // Annoy doesn't check item ids, and reading past the node array is undefined behavior.
+    fn assert_in_bounds(&self, item: u32) {
+        let n_items = self.get_n_items();
+        assert!(
+            item < n_items,
+            "Item {item} is out of bounds for an index with {n_items} items"
+        );
+    }
+
+    // Whether the index was set up with on_disk_build, so its nodes are that file's.
+    fn is_on_disk(&self) -> bool {
+        unsafe { annoy_angular_is_on_disk(self.ptr) }
+    }
+
+    // The raw node array backing a built or loaded index.
+    fn nodes(&self) -> Option<&[u8]> {
+        let mut size = 0;
+        let nodes = unsafe { annoy_angular_get_nodes(self.ptr, &mut size) };
+        if nodes.is_null() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts(nodes as *const u8, size) })
+    }
+}
+
+// Callers rely on query results coming back closest first, so check that Annoy kept its end of the
+// bargain in debug builds. NaN distances (from NaN vectors) don't have an order, so skip them.
+fn debug_assert_sorted(distances: &[f32]) {
+    debug_assert!(
+        distances
+            .windows(2)
+            .all(|w| w[0] <= w[1] || w[0].is_nan() || w[1].is_nan()),
+        "Query distances aren't sorted: {distances:?}"
+    );
+}
+
+// Paths on Unix are arbitrary bytes, so pass them to Annoy as-is. Elsewhere we only know how to
+// hand over UTF-8.
+#[cfg(unix)]
+fn path_to_cstring(p: &Path) -> anyhow::Result<CString> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(CString::new(p.as_os_str().as_bytes())?)
+}
+
+#[cfg(not(unix))]
+fn path_to_cstring(p: &Path) -> anyhow::Result<CString> {
+    let p_str = p
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Path {p:?} is not valid UTF-8"))?;
+    Ok(CString::new(p_str)?)
+}
+
+fn to_neighbors(results: Vec<u32>, distances: Vec<f32>, unit: DistanceUnit) -> Vec<Neighbor> {
+    results
+        .into_iter()
+        .zip(distances)
+        .map(|(id, distance)| Neighbor {
+            id,
+            distance: AngularDistance(distance).to_unit(unit),
+        })
+        .collect()
+}
+
+// The shim returns NULL instead of letting std::bad_alloc unwind into Rust.
+fn try_create_index(dimension: usize) -> anyhow::Result<*mut c_void> {
+    let f = c_int::try_from(dimension)
+        .map_err(|_| anyhow::anyhow!("create_index failed: dimension {dimension} is too large"))?;
+    let ptr = unsafe { annoy_angular_create_index(f) };
+    anyhow::ensure!(!ptr.is_null(), "create_index failed: out of memory");
+    Ok(ptr)
+}
+
+// reset has no way to report a failure, so panic.
+fn create_index(dimension: usize) -> *mut c_void {
+    try_create_index(dimension).unwrap_or_else(|err| panic!("{err}"))
+}
+
+// The temporary file next to p that save_durable and save_copy write before renaming it to p.
+fn tmp_path(p: &Path) -> anyhow::Result<PathBuf> {
+    let mut tmp_name = p
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Path {p:?} has no file name"))?
+        .to_owned();
+    tmp_name.push(".tmp");
+    Ok(p.with_file_name(tmp_name))
+}
+
+// Syncs the directory containing p, so that a file just created or renamed there is durable. Only
+// Unix can open a directory to sync it; elsewhere this does nothing.
+fn sync_parent_dir(p: &Path) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = match p.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = p;
+    Ok(())
+}
+
+fn no_error() -> annoy_error {
+    annoy_error {
+        message: ptr::null_mut(),
+        code: 0,
+    }
 }
 
-fn check_error(name: &str, success: bool, error_ptr: *mut c_char) -> anyhow::Result<()> {
+// Turns a failed shim's annoy_error into an AnnoyError::Ffi, freeing its message.
+fn check_error(operation: &'static str, success: bool, error: annoy_error) -> anyhow::Result<()> {
     if success {
         return Ok(());
     }
-    if error_ptr.is_null() {
-        anyhow::bail!("{name} failed: <unknown error>");
-    }
-    let message = unsafe { CStr::from_ptr(error_ptr).to_str()? };
-    let error = anyhow::anyhow!("{name} failed: {message}");
-    unsafe {
-        annoy_angular_free_error(error_ptr);
+    let message = if error.message.is_null() {
+        "<unknown error>".to_string()
+    } else {
+        let message = unsafe { CStr::from_ptr(error.message) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe {
+            annoy_angular_free_error(error.message);
+        }
+        message
+    };
+    Err(AnnoyError::Ffi {
+        operation,
+        code: error.code,
+        message,
     }
-    Err(error)
+    .into())
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
     use super::*;
+    use crate::testutil::temp_path;
 
     #[test]
     fn test_simple() -> anyhow::Result<()> {
@@ -248,10 +1704,1396 @@ mod tests {
             println!("{} {}", r, d);
         }
 
-        let (results, distance) = a.get_nearest_by_vector(&[1.0, 0.5, 0.5], 100, -1)?;
+        let (results, distance) = a.get_nearest_by_vector([1.0, 0.5, 0.5], 100, -1)?;
         for (r, d) in results.iter().zip(distance.iter()) {
             println!("{} {}", r, d);
         }
         Ok(())
     }
+
+    #[test]
+    fn test_reset() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.set_check_finite(true);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+
+        a.reset();
+        assert_eq!(a.get_n_items(), 0);
+        assert!(!a.check_finite);
+
+        a.add_item(0, &[0.0, 0.0, 1.0])?;
+        a.build(-1)?;
+        assert_eq!(a.get_n_items(), 1);
+        assert_eq!(a.get_item_vector(0), vec![0.0, 0.0, 1.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_disk_build_n_items() -> anyhow::Result<()> {
+        let path = temp_path("on_disk_build_n_items");
+        let mut a = AnnoyAngular::new(3);
+        a.on_disk_build(&path)?;
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.add_item(2, &[0.0, 0.0, 1.0])?;
+        a.build(-1)?;
+        assert_eq!(a.get_n_items(), 3);
+
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        assert_eq!(b.get_n_items(), 3);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_by_vector_map() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+
+        // Annoy's angular distance is sqrt(2 - 2 cos), so this maps it back to cosine similarity.
+        let results =
+            a.get_nearest_by_vector_map(&[1.0, 0.0, 0.0], 2, -1, |d| 1.0 - d * d / 2.0)?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, 0);
+        assert!((results[0].1 - 1.0).abs() < 1e-6);
+        assert_eq!(results[1].0, 1);
+        assert!(results[1].1.abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_by_vector_map_ids() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.add_item(2, &[1.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        let neighbors = a.get_nearest_by_vector_map_ids(&[1.0, 0.0, 0.0], 2, -1)?;
+        assert_eq!(neighbors.len(), 2);
+        assert!(neighbors[&0] < 1e-3);
+        assert!((neighbors[&2] - a.get_distance(0, 2).0).abs() < 1e-6);
+        assert!(!neighbors.contains_key(&1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_n_on_small_index() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.add_item(2, &[0.0, 0.0, 1.0])?;
+        a.build(-1)?;
+
+        let (results, distances) = a.get_nearest_by_item(0, 1_000_000, -1)?;
+        assert_eq!(results.len(), 3);
+        assert!(results.capacity() < 1_000_000);
+        assert!(distances.capacity() < 1_000_000);
+
+        let (results, distances) = a.get_nearest_by_vector([1.0, 0.5, 0.5], 1_000_000, -1)?;
+        assert_eq!(results.len(), 3);
+        assert!(results.capacity() < 1_000_000);
+        assert!(distances.capacity() < 1_000_000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_for_append() -> anyhow::Result<()> {
+        let path = temp_path("reopen_for_append");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        // Item 2 is a hole.
+        a.add_item(3, &[1.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save(&path)?;
+        drop(a);
+
+        let mut b = AnnoyAngular::reopen_for_append(&path, 3)?;
+        b.add_item(4, &[0.0, 0.0, 1.0])?;
+        b.build(-1)?;
+        assert_eq!(b.get_n_items(), 5);
+        assert_eq!(b.get_item_vector(0), vec![1.0, 0.0, 0.0]);
+        assert_eq!(b.get_item_vector(4), vec![0.0, 0.0, 1.0]);
+        // The hole is still a hole, not a zero vector that queries can return.
+        assert_eq!(b.item_vector_ref(2), None);
+        let (results, _) = b.get_nearest_by_vector([0.0, 0.0, 1.0], 10, -1)?;
+        assert_eq!(results.len(), 4);
+        assert!(!results.contains(&2));
+        let ids: Vec<u32> = b.iter_items().map(|(item, _)| item).collect();
+        assert_eq!(ids, vec![0, 1, 3, 4]);
+        b.save(&path)?;
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_by_missing_item() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+
+        assert!(a.get_nearest_by_item(1, 10, -1).is_ok());
+        assert!(a.get_nearest_by_item(2, 10, -1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        assert_eq!(
+            format!("{a:?}"),
+            r#"AnnoyAngular { dimension: 3, metric: "angular", built: false, n_items: 1, n_trees: 0 }"#
+        );
+
+        a.build(2)?;
+        assert_eq!(a.get_n_trees(), 2);
+        assert_eq!(
+            format!("{a:?}"),
+            r#"AnnoyAngular { dimension: 3, metric: "angular", built: true, n_items: 1, n_trees: 2 }"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_on_disk() -> anyhow::Result<()> {
+        let on_disk_path = temp_path("save_on_disk_build");
+        let save_path = temp_path("save_on_disk");
+        let mut a = AnnoyAngular::new(3);
+        a.on_disk_build(&on_disk_path)?;
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save(&save_path)?;
+        // The index is still the on_disk_build file; save wrote a copy.
+        assert_eq!(a.current_path(), Some(on_disk_path.as_path()));
+
+        let mut b = AnnoyAngular::new(3);
+        b.load(&save_path)?;
+        assert_eq!(b.get_n_items(), 2);
+        assert_eq!(b.get_item_vector(1), vec![0.0, 1.0, 0.0]);
+        assert_eq!(
+            b.get_nearest_by_item(0, 2, -1)?,
+            a.get_nearest_by_item(0, 2, -1)?
+        );
+
+        std::fs::remove_file(&on_disk_path)?;
+        std::fs::remove_file(&save_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_copy() -> anyhow::Result<()> {
+        let on_disk_path = temp_path("save_copy_on_disk");
+        let copy_path = temp_path("save_copy");
+
+        let mut a = AnnoyAngular::new(3);
+        assert!(a.save_copy(&copy_path).is_err());
+
+        a.on_disk_build(&on_disk_path)?;
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.add_item(2, &[0.0, 0.0, 1.0])?;
+        a.build(-1)?;
+        a.save_copy(&copy_path)?;
+
+        let mut b = AnnoyAngular::new(3);
+        b.load(&copy_path)?;
+        assert_eq!(b.get_n_items(), 3);
+        assert_eq!(b.get_n_trees(), a.get_n_trees());
+        assert_eq!(b.get_item_vector(1), vec![0.0, 1.0, 0.0]);
+        assert_eq!(
+            b.get_nearest_by_item(0, 3, -1)?,
+            a.get_nearest_by_item(0, 3, -1)?
+        );
+
+        std::fs::remove_file(&on_disk_path)?;
+        std::fs::remove_file(&copy_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_empty() {
+        let mut a = AnnoyAngular::new(3);
+        let err = a.build(-1).unwrap_err();
+        assert!(err.to_string().contains("0 items"), "{err}");
+    }
+
+    #[test]
+    fn test_flush() -> anyhow::Result<()> {
+        let path = temp_path("flush");
+        let mut a = AnnoyAngular::new(3);
+        a.on_disk_build(&path)?;
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.flush()?;
+
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        assert_eq!(b.get_n_items(), 2);
+        b.flush()?;
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_distance() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.add_item(2, &[1.0, 1.0, 0.0])?;
+        a.build(-1)?;
+
+        assert!((a.get_distance(0, 0).to_cosine_similarity() - 1.0).abs() < 1e-6);
+        assert!(a.get_distance(0, 1).to_cosine_similarity().abs() < 1e-6);
+        assert!(a.get_distance(0, 2) < a.get_distance(0, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_vectors() -> anyhow::Result<()> {
+        let a = AnnoyAngular::from_vectors(
+            &[
+                vec![1.0, 0.0, 0.0],
+                vec![0.0, 1.0, 0.0],
+                vec![0.0, 0.0, 1.0],
+            ],
+            -1,
+        )?;
+        assert_eq!(a.get_n_items(), 3);
+        assert_eq!(a.get_item_vector(2), vec![0.0, 0.0, 1.0]);
+
+        let err =
+            AnnoyAngular::from_vectors(&[vec![1.0, 0.0], vec![0.0, 1.0, 0.0]], -1).unwrap_err();
+        assert!(err.to_string().contains("vector 1"), "{err}");
+        assert!(AnnoyAngular::from_vectors(&[], -1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_built() -> anyhow::Result<()> {
+        let path = temp_path("is_built");
+        let mut a = AnnoyAngular::new(3);
+        assert!(!a.is_built());
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.build(-1)?;
+        assert!(a.is_built());
+
+        let err = a.add_item(1, &[0.0, 1.0, 0.0]).unwrap_err();
+        assert!(err.to_string().contains("built index"), "{err}");
+
+        a.save(&path)?;
+        assert!(a.is_built());
+        a.unload();
+        assert!(!a.is_built());
+        a.load(&path)?;
+        assert!(a.is_built());
+        a.reset();
+        assert!(!a.is_built());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_dimension_mismatch() -> anyhow::Result<()> {
+        let path = temp_path("load_dimension_mismatch");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.add_item(2, &[0.0, 0.0, 1.0])?;
+        a.build(-1)?;
+        a.save(&path)?;
+
+        let mut b = AnnoyAngular::new(4);
+        let err = b.load(&path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AnnoyError>(),
+            Some(AnnoyError::DimensionMismatch { dimension: 4, .. })
+        ));
+        assert!(!b.is_built());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_disk_build_streams_items() -> anyhow::Result<()> {
+        let path = temp_path("on_disk_build_streams_items");
+        let mut a = AnnoyAngular::new(8);
+        a.on_disk_build(&path)?;
+        for i in 0..1000 {
+            a.add_item(i, &[i as f32, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0])?;
+        }
+        // The items are staged in the file rather than in RAM before build is called.
+        let staged_size = std::fs::metadata(&path)?.len();
+        assert!(staged_size >= 1000 * format::node_size(8));
+
+        a.build(10)?;
+        assert_eq!(a.get_n_items(), 1000);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_item_normalized() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(2);
+        a.add_item_normalized(0, &[3.0, 4.0])?;
+        assert_eq!(a.get_item_vector(0), vec![0.6, 0.8]);
+        assert!(a.add_item_normalized(1, &[0.0, 0.0]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_with_stats() -> anyhow::Result<()> {
+        let vectors: Vec<Vec<f32>> = (0..100)
+            .map(|i| vec![(i as f32).sin(), (i as f32).cos(), i as f32 / 100.0])
+            .collect();
+        let a = AnnoyAngular::from_vectors(&vectors, 5)?;
+
+        let query = [1.0, 0.5, 0.5];
+        let (neighbors, stats) = a.query_with_stats(&query, 10, -1)?;
+        let (results, distances) = a.get_nearest_by_vector(query, 10, -1)?;
+        assert_eq!(neighbors.iter().map(|n| n.id).collect::<Vec<_>>(), results);
+        assert_eq!(
+            neighbors.iter().map(|n| n.distance).collect::<Vec<_>>(),
+            distances
+        );
+        assert_eq!(stats.trees_used, 5);
+        assert!(stats.nodes_visited >= stats.trees_used);
+        assert!(stats.n_candidates >= neighbors.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_items_from_slice() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 1.0, 0.0])?;
+        let data = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+        assert_eq!(a.add_items_from_slice(&data, 3)?, 1..4);
+        assert_eq!(a.get_n_items(), 4);
+        assert_eq!(a.get_item_vector(2), vec![0.0, 1.0, 0.0]);
+        assert_eq!(a.add_items_from_slice(&[], 3)?, 4..4);
+
+        assert!(a.add_items_from_slice(&data[..4], 3).is_err());
+        assert!(a.add_items_from_slice(&data[..4], 2).is_err());
+        a.build(-1)?;
+        assert_eq!(a.get_nearest_by_vector([0.0, 0.0, 1.0], 1, -1)?.0, vec![3]);
+        assert!(a.add_items_from_slice(&data, 3).is_err());
+
+        let mut b = AnnoyAngular::new_with_capacity(3, 2)?;
+        assert!(b.add_items_from_slice(&data, 3).is_err());
+        assert_eq!(b.add_items_from_slice(&data[..6], 3)?, 0..2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unchecked() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        unsafe {
+            a.add_item_unchecked(0, &[1.0, 0.0, 0.0])?;
+            a.add_item_unchecked(1, &[0.0, 1.0, 0.0])?;
+        }
+        a.build(-1)?;
+        let (results, _) = unsafe { a.get_nearest_by_vector_unchecked(&[0.0, 1.0, 0.0], 1, -1)? };
+        assert_eq!(results, vec![1]);
+        // The other checks still apply.
+        assert!(unsafe { a.add_item_unchecked(2, &[0.0, 0.0, 1.0]) }.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_finite() -> anyhow::Result<()> {
+        // Unchecked by default.
+        AnnoyAngular::new(3).add_item(0, &[f32::NAN, 0.0, 0.0])?;
+
+        let mut a = AnnoyAngular::new(3);
+        a.set_check_finite(true);
+        let err = a.add_item(1, &[1.0, f32::NAN, 0.0]).unwrap_err();
+        assert_eq!(err.to_string(), "add_item failed: component 1 is NaN");
+        assert!(a.add_item(1, &[1.0, f32::INFINITY, 0.0]).is_err());
+        assert!(a
+            .add_items_from_slice(&[0.0, 1.0, f32::NEG_INFINITY], 3)
+            .is_err());
+        assert_eq!(a.get_n_items(), 1);
+        a.add_item(1, &[1.0, 0.0, 0.0])?;
+        a.add_item(2, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+
+        let err = a
+            .get_nearest_by_vector([0.0, 0.0, f32::NAN], 1, -1)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "get_nearest_by_vector failed: component 2 is NaN"
+        );
+        assert!(a
+            .get_nearest_ids_by_vector(&[f32::INFINITY, 0.0, 0.0], 1, -1)
+            .is_err());
+        assert_eq!(
+            a.get_nearest_ids_by_vector(&[0.0, 1.0, 0.0], 1, -1)?,
+            vec![2]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        assert_eq!(unsafe { annoy_angular_get_n_items(a.as_raw()) }, 2);
+
+        let b = unsafe { AnnoyAngular::from_raw(a.into_raw(), 3) };
+        assert!(!b.is_built());
+        assert_eq!(b.get_item_vector(1), vec![0.0, 1.0, 0.0]);
+
+        let mut c = AnnoyAngular::new(3);
+        c.add_item(0, &[1.0, 0.0, 0.0])?;
+        c.build(1)?;
+        let c = unsafe { AnnoyAngular::from_raw(c.into_raw(), 3) };
+        assert!(c.is_built());
+        assert_eq!(c.get_nearest_by_item(0, 1, -1)?.0, vec![0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_leaf_size() {
+        assert_eq!(AnnoyAngular::new(3).max_leaf_size(), 5);
+        assert_eq!(AnnoyAngular::new(100).max_leaf_size(), 102);
+    }
+
+    #[test]
+    fn test_try_new() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::try_new(3)?;
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        assert_eq!(a.get_n_items(), 1);
+
+        let err = AnnoyAngular::try_new(usize::MAX).unwrap_err();
+        assert!(err.to_string().starts_with("create_index failed"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_by_items() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(2);
+        a.add_item(0, &[1.0, 0.0])?;
+        a.add_item(1, &[0.0, 10.0])?;
+        a.add_item(2, &[1.0, 1.0])?;
+        a.add_item(3, &[-1.0, 0.0])?;
+        a.add_item(5, &[1.0, -1.0])?;
+        a.build(-1)?;
+
+        // Scaled to unit length, items 0 and 1 average to the direction of item 2.
+        let (results, distances) = a.get_nearest_by_items(&[0, 1], 1, -1)?;
+        assert_eq!(results, vec![2]);
+        assert!(distances[0] < 1e-3);
+        assert_eq!(a.get_nearest_by_items(&[5], 1, -1)?.0, vec![5]);
+
+        assert!(a.get_nearest_by_items(&[], 1, -1).is_err());
+        assert!(a.get_nearest_by_items(&[0, 4], 1, -1).is_err());
+        assert!(a.get_nearest_by_items(&[0, 6], 1, -1).is_err());
+        assert!(a.get_nearest_by_items(&[0, 3], 1, -1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_load() -> anyhow::Result<()> {
+        let path = temp_path("try_load");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save(&path)?;
+        let b = AnnoyAngular::try_load(&path, 3)?;
+        assert_eq!(b.get_nearest_by_item(1, 1, -1)?.0, vec![1]);
+
+        // A save that was cut short partway through a node.
+        let bytes = std::fs::read(&path)?;
+        std::fs::write(&path, &bytes[..bytes.len() - 7])?;
+        let err = AnnoyAngular::try_load(&path, 3).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AnnoyError>(),
+            Some(AnnoyError::CorruptIndex { .. })
+        ));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_n_nodes() -> anyhow::Result<()> {
+        let path = temp_path("get_n_nodes");
+        let mut a = AnnoyAngular::new(3);
+        for i in 0..100 {
+            a.add_item(i, &[i as f32, 1.0, -(i as f32)])?;
+        }
+        assert_eq!(a.get_n_nodes(), 100);
+        a.build(5)?;
+        let n_nodes = a.get_n_nodes();
+        // The items, at least one split node per tree and the roots' copies.
+        assert!(n_nodes >= 100 + 5 + 5);
+        assert_eq!(a.stats()?.bytes, n_nodes * 24);
+
+        a.save(&path)?;
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        assert_eq!(b.get_n_nodes(), n_nodes);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_distance_matrix() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(2);
+        a.add_item(0, &[1.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0])?;
+        a.add_item(3, &[-1.0, 0.0])?;
+
+        let matrix = a.distance_matrix(&[3, 0, 1])?;
+        assert_eq!(matrix.len(), 3);
+        for (k, row) in matrix.iter().enumerate() {
+            assert_eq!(row[k], 0.0);
+            for (l, &distance) in row.iter().enumerate() {
+                assert_eq!(distance, matrix[l][k]);
+            }
+        }
+        assert!((matrix[0][1] - 2.0).abs() < 1e-6);
+        assert!((matrix[1][2] - 2f32.sqrt()).abs() < 1e-6);
+        assert!(a.distance_matrix(&[])?.is_empty());
+
+        assert!(a.distance_matrix(&[0, 2]).is_err());
+        assert!(a.distance_matrix(&[0, 4]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_durable() -> anyhow::Result<()> {
+        let path = temp_path("save_durable");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save_durable(&path)?;
+        assert_eq!(a.current_path(), Some(path.as_path()));
+        assert!(!tmp_path(&path)?.exists());
+        assert_eq!(a.get_nearest_by_item(1, 1, -1)?.0, vec![1]);
+
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        assert_eq!(b.get_n_items(), 2);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_durable_stale_tmp() -> anyhow::Result<()> {
+        let path = temp_path("save_durable_stale_tmp");
+        // Left over from a save that crashed partway through.
+        std::fs::write(tmp_path(&path)?, [0u8; 10])?;
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save_durable(&path)?;
+
+        let b = AnnoyAngular::try_load(&path, 3)?;
+        assert_eq!(b.get_item_vector(1), vec![0.0, 1.0, 0.0]);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_durable_on_disk() -> anyhow::Result<()> {
+        let on_disk_path = temp_path("save_durable_on_disk_build");
+        let path = temp_path("save_durable_on_disk");
+        std::fs::write(tmp_path(&path)?, [0u8; 10])?;
+        let mut a = AnnoyAngular::new(3);
+        a.on_disk_build(&on_disk_path)?;
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save_durable(&path)?;
+        assert_eq!(a.current_path(), Some(on_disk_path.as_path()));
+        assert!(!tmp_path(&path)?.exists());
+
+        let b = AnnoyAngular::try_load(&path, 3)?;
+        assert_eq!(b.get_n_items(), 2);
+        assert_eq!(b.get_item_vector(1), vec![0.0, 1.0, 0.0]);
+        std::fs::remove_file(&on_disk_path)?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_vectors() -> anyhow::Result<()> {
+        let data_path = temp_path("export_vectors_data");
+        let ids_path = temp_path("export_vectors_ids");
+        let mut a = AnnoyAngular::new(2);
+        a.add_item(0, &[1.0, 2.0])?;
+        a.add_item(3, &[3.0, 4.0])?;
+        a.export_vectors(&data_path, &ids_path)?;
+
+        let data: Vec<f32> = std::fs::read(&data_path)?
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+            .collect();
+        let ids: Vec<u32> = std::fs::read(&ids_path)?
+            .chunks_exact(4)
+            .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(data, vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(ids, vec![0, 3]);
+
+        let mut b = AnnoyAngular::new(2);
+        for (id, vector) in ids.iter().zip(data.chunks_exact(2)) {
+            b.add_item(*id, vector)?;
+        }
+        assert_eq!(b.get_n_items(), 4);
+        assert_eq!(b.get_item_vector(3), vec![3.0, 4.0]);
+        std::fs::remove_file(&data_path)?;
+        std::fs::remove_file(&ids_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserve() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.reserve(1000)?;
+        assert_eq!(a.get_n_items(), 0);
+        for i in 0..1000 {
+            a.add_item(i, &[i as f32, 1.0, 0.0])?;
+        }
+        assert_eq!(a.get_n_items(), 1000);
+        a.build(2)?;
+        assert!(a.reserve(2000).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "aren't sorted")]
+    fn test_debug_assert_sorted() {
+        debug_assert_sorted(&[0.0, 0.5, 0.5, f32::NAN, 1.0]);
+        debug_assert_sorted(&[0.0, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn test_on_disk_build_with_scratch() -> anyhow::Result<()> {
+        let path = temp_path("on_disk_build_with_scratch");
+        let scratch_dir = temp_path("scratch");
+        std::fs::create_dir_all(&scratch_dir)?;
+
+        let mut a = AnnoyAngular::new(3);
+        a.on_disk_build_with_scratch(&path, &scratch_dir)?;
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        assert!(!path.exists());
+        a.build(-1)?;
+        assert!(path.exists());
+        assert_eq!(std::fs::read_dir(&scratch_dir)?.count(), 0);
+
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        assert_eq!(b.get_n_items(), 2);
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_dir(&scratch_dir)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path() -> anyhow::Result<()> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let mut path = temp_path("non_utf8").into_os_string();
+        path.push(OsStr::from_bytes(b".\xff"));
+        let path = PathBuf::from(path);
+        assert!(path.to_str().is_none());
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save(&path)?;
+
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        assert_eq!(b.get_n_items(), 2);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_by_owned_vector() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        let query = vec![0.0, 1.0, 0.1];
+        let (results, _) = a.get_nearest_by_vector(query, 1, -1)?;
+        assert_eq!(results, vec![1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() -> anyhow::Result<()> {
+        let path = temp_path("verify");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        assert!(a.verify().is_err());
+        a.build(-1)?;
+        a.verify()?;
+        a.save(&path)?;
+
+        let mut b = AnnoyAngular::new(3);
+        assert!(b.verify().is_err());
+        b.load(&path)?;
+        b.verify()?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_with_capacity() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new_with_capacity(3, 2)?;
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        assert!(a.add_item(2, &[0.0, 0.0, 1.0]).is_err());
+        a.build(-1)?;
+        assert_eq!(a.get_n_items(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_within_memory() -> anyhow::Result<()> {
+        let vectors = crate::testutil::random_vectors(1000, 8, 1);
+        let mut a = AnnoyAngular::new(8);
+        for (i, vector) in vectors.iter().enumerate() {
+            a.add_item(i as u32, vector)?;
+        }
+        assert!(a.build_within_memory(1000).is_err());
+        assert!(!a.is_built());
+
+        let max_bytes = estimated_build_memory(1000, 8, 5);
+        assert_eq!(a.build_within_memory(max_bytes)?, 5);
+        assert_eq!(a.get_n_trees(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_with_progress() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        let mut progress = vec![];
+        a.build_with_progress(3, |n| progress.push(n))?;
+        assert_eq!(progress, vec![1, 2, 3]);
+        assert_eq!(a.get_n_trees(), 3);
+        assert!(a.is_built());
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_with_progress_matches_build() -> anyhow::Result<()> {
+        let vectors = crate::testutil::random_vectors(500, 8, 1);
+        for n_trees in [5, -1] {
+            let mut a = AnnoyAngular::new(8);
+            let mut b = AnnoyAngular::new(8);
+            for (i, vector) in vectors.iter().enumerate() {
+                a.add_item(i as u32, vector)?;
+                b.add_item(i as u32, vector)?;
+            }
+            a.build(n_trees)?;
+            b.build_with_progress(n_trees, |_| {})?;
+            assert_eq!(a.nodes(), b.nodes());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_with_advice() -> anyhow::Result<()> {
+        let path = temp_path("load_with_advice");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save(&path)?;
+
+        for advice in [
+            MmapAdvice::Normal,
+            MmapAdvice::Random,
+            MmapAdvice::Sequential,
+            MmapAdvice::WillNeed,
+        ] {
+            let mut b = AnnoyAngular::new(3);
+            b.load_with_advice(&path, advice)?;
+            let (results, _) = b.get_nearest_by_item(0, 1, -1)?;
+            assert_eq!(results, vec![0]);
+        }
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_warm() -> anyhow::Result<()> {
+        let path = temp_path("warm");
+        let mut a = AnnoyAngular::new(3);
+        // Nothing to warm yet.
+        a.warm();
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save(&path)?;
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        b.warm();
+        assert_eq!(b.get_nearest_by_item(0, 1, -1)?.0, vec![0]);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_current_path() -> anyhow::Result<()> {
+        let path = temp_path("current_path");
+        let mut a = AnnoyAngular::new(3);
+        assert_eq!(a.current_path(), None);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.build(-1)?;
+        assert_eq!(a.current_path(), None);
+        a.save(&path)?;
+        assert_eq!(a.current_path(), Some(path.as_path()));
+        assert_eq!(a.try_clone()?.current_path(), None);
+        a.unload();
+        assert_eq!(a.current_path(), None);
+        a.load(&path)?;
+        assert_eq!(a.current_path(), Some(path.as_path()));
+        a.reset();
+        assert_eq!(a.current_path(), None);
+        std::fs::remove_file(&path)?;
+
+        let scratch_dir = temp_path("current_path_scratch");
+        std::fs::create_dir_all(&scratch_dir)?;
+        let mut b = AnnoyAngular::new(3);
+        b.on_disk_build_with_scratch(&path, &scratch_dir)?;
+        assert_eq!(
+            b.current_path(),
+            Some(scratch_dir.join(path.file_name().unwrap()).as_path())
+        );
+        b.add_item(0, &[1.0, 0.0, 0.0])?;
+        b.build(-1)?;
+        assert_eq!(b.current_path(), Some(path.as_path()));
+        std::fs::remove_file(&path)?;
+        std::fs::remove_dir(&scratch_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_backing() -> anyhow::Result<()> {
+        let path = temp_path("backing");
+        let mut a = AnnoyAngular::new(3);
+        assert_eq!(a.backing(), Backing::Ram);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.build(-1)?;
+        assert_eq!(a.backing(), Backing::Ram);
+        a.save(&path)?;
+        assert_eq!(a.backing(), Backing::Mmap);
+        a.unload();
+        assert_eq!(a.backing(), Backing::Ram);
+        a.load(&path)?;
+        assert_eq!(a.backing(), Backing::Mmap);
+        assert_eq!(a.try_clone()?.backing(), Backing::Ram);
+        std::fs::remove_file(&path)?;
+
+        let mut b = AnnoyAngular::new(3);
+        b.on_disk_build(&path)?;
+        assert_eq!(b.backing(), Backing::Mmap);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_unbuilt() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        assert!(a.get_nearest_by_item(0, 1, -1).is_err());
+        assert!(a.get_nearest_by_vector([1.0, 0.0, 0.0], 1, -1).is_err());
+        assert!(a.query_with_stats(&[1.0, 0.0, 0.0], 1, -1).is_err());
+        a.build(-1)?;
+        assert!(a.get_nearest_by_item(0, 1, -1).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(10)?;
+        assert_eq!(a.get_n_trees(), 10);
+        a.rebuild(50)?;
+        assert_eq!(a.get_n_trees(), 50);
+        assert_eq!(a.get_n_items(), 2);
+
+        a.unbuild()?;
+        assert!(!a.is_built());
+        a.add_item(2, &[0.0, 0.0, 1.0])?;
+        a.build(5)?;
+        assert_eq!(a.get_n_items(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_within_distance() -> anyhow::Result<()> {
+        // 100 unit vectors spread over a quarter circle, so item i is at angle i * pi / 198 from
+        // the x axis.
+        let vectors: Vec<Vec<f32>> = (0..100)
+            .map(|i| {
+                let theta = i as f32 * std::f32::consts::PI / 198.0;
+                vec![theta.cos(), theta.sin(), 0.0]
+            })
+            .collect();
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+
+        let max_distance = 0.5;
+        // Search the whole forest so the approximation doesn't lose any items.
+        let neighbors = a.get_within_distance(&[1.0, 0.0, 0.0], max_distance, 100_000)?;
+        let expected = (0..100)
+            .filter(|&i| a.get_distance(0, i).0 <= max_distance)
+            .count();
+        assert!(neighbors.len() > 16);
+        assert_eq!(neighbors.len(), expected);
+        assert!(neighbors.iter().all(|n| n.distance <= max_distance));
+
+        assert_eq!(a.get_within_distance(&[1.0, 0.0, 0.0], 2.0, -1)?.len(), 100);
+
+        // With the default search_k too, every item in range is found, in order, even though each
+        // round searches a different amount of the forest.
+        let query = [1.0, 0.2, 0.0];
+        let neighbors = a.get_within_distance(&query, max_distance, -1)?;
+        let exact: Vec<Neighbor> = a
+            .get_nearest_by_vector_exact(&query, 100)?
+            .into_iter()
+            .filter(|n| n.distance <= max_distance)
+            .collect();
+        assert!(exact.len() > 16);
+        assert_eq!(
+            neighbors.iter().map(|n| n.id).collect::<Vec<_>>(),
+            exact.iter().map(|n| n.id).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_within_distance() -> anyhow::Result<()> {
+        let vectors = crate::testutil::random_vectors(200, 8, 1);
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+        let query = &vectors[0];
+        for search_k in [-1, 10_000] {
+            for max_distance in [0.0, 0.5, 1.0, 2.0] {
+                assert_eq!(
+                    a.count_within_distance(query, max_distance, search_k)?,
+                    a.get_within_distance(query, max_distance, search_k)?.len()
+                );
+            }
+        }
+        // Angular distances are at most 2, so everything is in range.
+        assert_eq!(a.count_within_distance(query, 2.5, 10_000)?, 200);
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_clone() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+
+        // An unbuilt copy can be changed independently.
+        let mut b = a.try_clone()?;
+        b.add_item(2, &[0.0, 0.0, 1.0])?;
+        assert_eq!(a.get_n_items(), 2);
+        assert_eq!(b.get_n_items(), 3);
+
+        a.build(5)?;
+        let c = a.try_clone()?;
+        assert!(c.is_built());
+        assert_eq!(c.get_n_trees(), 5);
+        assert_eq!(c.get_item_vector(1), a.get_item_vector(1));
+        drop(a);
+        let (results, _) = c.get_nearest_by_vector([0.0, 1.0, 0.1], 1, -1)?;
+        assert_eq!(results, vec![1]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ffi_error_code() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.build(-1)?;
+        let err = a
+            .save(Path::new("/nonexistent-annoy-rs-dir/index.ann"))
+            .unwrap_err();
+        match err.downcast_ref::<AnnoyError>() {
+            Some(AnnoyError::Ffi {
+                operation, code, ..
+            }) => {
+                assert_eq!(*operation, "save");
+                assert_eq!(*code, libc::ENOENT);
+            }
+            other => panic!("Unexpected error: {other:?}"),
+        }
+
+        // Failures that don't come from a system call have no code.
+        let path = temp_path("ffi_error_code");
+        a.save(&path)?;
+        let err = a.unbuild().unwrap_err();
+        match err.downcast_ref::<AnnoyError>() {
+            Some(AnnoyError::Ffi {
+                operation, code, ..
+            }) => {
+                assert_eq!(*operation, "unbuild");
+                assert_eq!(*code, 0);
+            }
+            other => panic!("Unexpected error: {other:?}"),
+        }
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_failure_is_err() -> anyhow::Result<()> {
+        let path = temp_path("build_failure_is_err");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.save(&path)?;
+
+        // Annoy refuses to build a loaded index, and only the C++ side checks for that, so the
+        // failure has to make it back across the FFI boundary.
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        for err in [
+            b.build(-1).unwrap_err(),
+            b.build_with_progress(-1, |_| {}).unwrap_err(),
+        ] {
+            match err.downcast_ref::<AnnoyError>() {
+                Some(AnnoyError::Ffi { operation, .. }) => assert_eq!(*operation, "build"),
+                other => panic!("Unexpected error: {other:?}"),
+            }
+        }
+
+        // The index is still usable afterwards.
+        assert_eq!(b.get_nearest_by_item(0, 1, -1)?.0, vec![0]);
+        assert_eq!(b.get_nearest_by_vector_exact(&[0.0, 1.0, 0.0], 1)?[0].id, 1);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> anyhow::Result<()> {
+        let path = temp_path("stats");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        assert_eq!(a.stats()?.bytes, 2 * format::node_size(3));
+        a.build(2)?;
+        let stats = a.stats()?;
+        assert_eq!(stats.n_items, 2);
+        assert_eq!(stats.n_trees, 2);
+        assert_eq!(stats.dimension, 3);
+        assert_eq!(stats.metric, "angular");
+        a.save(&path)?;
+
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        assert_eq!(b.stats()?, stats);
+        assert_eq!(stats.bytes, std::fs::metadata(&path)?.len());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_after_on_disk_build() -> anyhow::Result<()> {
+        let path = temp_path("query_after_on_disk_build");
+        let mut a = AnnoyAngular::new(3);
+        a.on_disk_build(&path)?;
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+
+        let (results, _) = a.get_nearest_by_vector([0.0, 1.0, 0.1], 1, -1)?;
+        assert_eq!(results, vec![1]);
+        let (results, _) = a.get_nearest_by_item(0, 2, -1)?;
+        assert_eq!(results, vec![0, 1]);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_in_unit() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(2);
+        a.add_item(0, &[1.0, 0.0])?;
+        a.add_item(1, &[1.0, 1.0])?;
+        a.build(-1)?;
+
+        let neighbors =
+            a.get_nearest_by_vector_in(&[1.0, 0.0], 2, -1, DistanceUnit::CosineDistance)?;
+        assert_eq!(neighbors[0].id, 0);
+        assert!(neighbors[0].distance.abs() < 1e-6);
+        assert!((neighbors[1].distance - (1.0 - 1.0 / 2f32.sqrt())).abs() < 1e-6);
+
+        let neighbors = a.get_nearest_by_item_in(0, 2, -1, DistanceUnit::CosineSimilarity)?;
+        assert!((neighbors[0].distance - 1.0).abs() < 1e-6);
+        assert!((neighbors[1].distance - 1.0 / 2f32.sqrt()).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_send() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        let a = std::thread::spawn(move || -> anyhow::Result<AnnoyAngular> {
+            a.build(-1)?;
+            Ok(a)
+        })
+        .join()
+        .unwrap()?;
+        assert!(a.is_built());
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_build_in_pool() -> anyhow::Result<()> {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build()?;
+        let pool = &pool;
+        let mut indexes = (0..8)
+            .map(|i| {
+                let mut a = AnnoyAngular::new(3);
+                a.add_item(0, &[1.0, i as f32, 0.0])?;
+                a.add_item(1, &[0.0, 1.0, i as f32])?;
+                Ok(a)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        std::thread::scope(|s| {
+            let handles: Vec<_> = indexes
+                .iter_mut()
+                .map(|a| s.spawn(move || a.build_in_pool(5, pool)))
+                .collect();
+            handles
+                .into_iter()
+                .try_for_each(|handle| handle.join().unwrap())
+        })?;
+        assert!(indexes.iter().all(|a| a.get_n_trees() == 5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_negative_trees() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        assert!(a.build(-2).is_err());
+        assert!(!a.is_built());
+        a.build(-1)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_push() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        assert_eq!(a.push(&[1.0, 0.0, 0.0])?, 0);
+        assert_eq!(a.push(&[0.0, 1.0, 0.0])?, 1);
+        a.add_item(5, &[0.0, 0.0, 1.0])?;
+        assert_eq!(a.push(&[1.0, 1.0, 0.0])?, 6);
+        assert!(a.push(&[1.0, 1.0]).is_err());
+        assert_eq!(a.get_n_items(), 7);
+        assert_eq!(a.get_item_vector(6), vec![1.0, 1.0, 0.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_item_vector_ref() -> anyhow::Result<()> {
+        let path = temp_path("item_vector_ref");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(2, &[0.0, 1.0, 0.0])?;
+        assert_eq!(a.item_vector_ref(0), Some(&[1.0, 0.0, 0.0][..]));
+        assert_eq!(a.item_vector_ref(1), None);
+        assert_eq!(a.item_vector_ref(3), None);
+        a.build(-1)?;
+        a.save(&path)?;
+
+        let mut b = AnnoyAngular::new(3);
+        b.load(&path)?;
+        assert_eq!(b.item_vector_ref(2), Some(&[0.0, 1.0, 0.0][..]));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_nearest() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        assert!(a.nearest(&[0.0, 1.0, 0.1], -1).is_err());
+        a.build(-1)?;
+        let neighbor = a.nearest(&[0.0, 1.0, 0.1], -1)?.unwrap();
+        assert_eq!(neighbor.id, 1);
+        assert!(neighbor.distance < 0.2);
+        // A search that isn't allowed to look at any candidates finds nothing.
+        assert_eq!(a.nearest(&[0.0, 1.0, 0.1], 0)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_ids_by_vector() -> anyhow::Result<()> {
+        let vectors = crate::testutil::random_vectors(100, 8, 1);
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+        let (results, _) = a.get_nearest_by_vector(&vectors[0], 10, -1)?;
+        assert_eq!(a.get_nearest_ids_by_vector(&vectors[0], 10, -1)?, results);
+        assert!(a.get_nearest_ids_by_vector(&[1.0], 10, -1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_by_vector_into() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.add_item(2, &[0.0, 0.0, 1.0])?;
+        a.build(-1)?;
+
+        let mut results = vec![42];
+        let mut distances = vec![4.2];
+        a.get_nearest_by_vector_into(&[0.0, 1.0, 0.1], 2, -1, &mut results, &mut distances)?;
+        assert_eq!(results, vec![1, 2]);
+        assert_eq!(distances.len(), 2);
+
+        let capacity = results.capacity();
+        a.get_nearest_by_vector_into(&[1.0, 0.1, 0.0], 2, -1, &mut results, &mut distances)?;
+        assert_eq!(results, vec![0, 1]);
+        assert_eq!(results.capacity(), capacity);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_after_unload() -> anyhow::Result<()> {
+        let path = temp_path("query_after_unload");
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(-1)?;
+        a.unload();
+        assert!(a.get_nearest_by_item(0, 1, -1).is_err());
+        assert!(a.get_nearest_by_vector([1.0, 0.0, 0.0], 1, -1).is_err());
+        assert!(a.query_with_stats(&[1.0, 0.0, 0.0], 1, -1).is_err());
+        assert!(std::panic::catch_unwind(|| a.get_item_vector(0)).is_err());
+
+        // The unloaded index is empty and usable again.
+        a.add_item(0, &[0.0, 0.0, 1.0])?;
+        a.build(-1)?;
+        a.save(&path)?;
+        a.unload();
+        assert!(a.get_nearest_by_item(0, 1, -1).is_err());
+        a.load(&path)?;
+        assert_eq!(a.get_nearest_by_item(0, 1, -1)?.0, vec![0]);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.build(5)?;
+        let mut b = AnnoyAngular::new(3);
+        b.add_item(0, &[0.0, 0.0, 1.0])?;
+        // Item 1 of b is a hole.
+        b.add_item(2, &[1.0, 1.0, 0.0])?;
+        b.build(10)?;
+
+        assert!(AnnoyAngular::merge(&a, &b, 1).is_err());
+        assert!(AnnoyAngular::merge(&a, &AnnoyAngular::new(4), 2).is_err());
+
+        let merged = AnnoyAngular::merge(&a, &b, 100)?;
+        assert_eq!(merged.get_n_items(), 103);
+        assert_eq!(merged.get_n_trees(), 10);
+        assert_eq!(merged.item_vector_ref(1), Some(&[0.0, 1.0, 0.0][..]));
+        assert_eq!(merged.item_vector_ref(100), Some(&[0.0, 0.0, 1.0][..]));
+        assert_eq!(merged.item_vector_ref(101), None);
+        assert_eq!(merged.item_vector_ref(102), Some(&[1.0, 1.0, 0.0][..]));
+        assert_eq!(merged.nearest(&[0.0, 0.1, 1.0], -1)?.unwrap().id, 100);
+
+        // Unbuilt inputs have no trees to match, so the merged index gets Annoy's default.
+        let mut c = AnnoyAngular::new(3);
+        c.add_item(0, &[1.0, 0.0, 0.0])?;
+        let mut d = AnnoyAngular::new(3);
+        d.add_item(0, &[0.0, 1.0, 0.0])?;
+        let merged = AnnoyAngular::merge(&c, &d, 1)?;
+        assert!(merged.get_n_trees() > 0);
+        assert_eq!(merged.nearest(&[0.1, 1.0, 0.0], -1)?.unwrap().id, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retain() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.0, 1.0, 0.0])?;
+        a.add_item(2, &[0.0, 0.0, 1.0])?;
+        a.add_item(4, &[1.0, 1.0, 0.0])?;
+        a.build(5)?;
+
+        let b = a.retain(|id| id != 1 && id != 4, 3)?;
+        assert_eq!(a.get_n_items(), 5);
+        assert_eq!(b.get_n_items(), 3);
+        assert_eq!(b.get_n_trees(), 3);
+        assert_eq!(b.item_vector_ref(1), None);
+        assert_eq!(b.item_vector_ref(2), Some(&[0.0, 0.0, 1.0][..]));
+        assert_eq!(b.nearest(&[0.1, 1.0, 0.0], -1)?.unwrap().id, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_nearest() -> anyhow::Result<()> {
+        let mut a = AnnoyAngular::new(3);
+        a.add_item(0, &[1.0, 0.0, 0.0])?;
+        a.add_item(1, &[0.9, 0.1, 0.0])?;
+        // Item 2 is a hole.
+        a.add_item(3, &[0.1, 0.0, 1.0])?;
+        assert!(a.all_nearest(2, -1).is_err());
+        a.build(10)?;
+
+        let all: Vec<(u32, Vec<Neighbor>)> =
+            a.all_nearest(2, -1)?.collect::<anyhow::Result<_>>()?;
+        let ids: Vec<(u32, Vec<u32>)> = all
+            .iter()
+            .map(|(item, neighbors)| (*item, neighbors.iter().map(|n| n.id).collect()))
+            .collect();
+        assert_eq!(ids, vec![(0, vec![0, 1]), (1, vec![1, 0]), (3, vec![3, 0])]);
+        for (item, neighbors) in &all {
+            let (_, distances) = a.get_nearest_by_item(*item, 2, -1)?;
+            assert_eq!(
+                neighbors.iter().map(|n| n.distance).collect::<Vec<_>>(),
+                distances
+            );
+        }
+        Ok(())
+    }
 }
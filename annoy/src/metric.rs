@@ -0,0 +1,72 @@
+use space::Metric;
+
+use crate::AngularDistance;
+
+// Annoy's angular metric for code written against the space crate's Metric trait, so that an
+// AnnoyAngular can be swapped for (or checked against) another nearest neighbor backend. The
+// distance is the one AnnoyAngular's queries and get_distance report, as the bits of the f32:
+// Metric wants an unsigned integer, and the distance is never negative, so its bits sort in the
+// same order as the float does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AngularMetric;
+
+impl AngularMetric {
+    // AngularMetric.angular_distance(u, v) computes the distance between u and v the way Annoy
+    // does. Zero vectors are at distance sqrt(2) from everything, including each other.
+    pub fn angular_distance(&self, u: &[f32], v: &[f32]) -> AngularDistance {
+        let (mut pp, mut qq, mut pq) = (0.0f32, 0.0f32, 0.0f32);
+        for (x, y) in u.iter().zip(v) {
+            pp += x * x;
+            qq += y * y;
+            pq += x * y;
+        }
+        let ppqq = pp * qq;
+        let d = if ppqq > 0.0 {
+            2.0 - 2.0 * pq / ppqq.sqrt()
+        } else {
+            2.0
+        };
+        AngularDistance(d.max(0.0).sqrt())
+    }
+}
+
+impl Metric<Vec<f32>> for AngularMetric {
+    type Unit = u32;
+
+    fn distance(&self, a: &Vec<f32>, b: &Vec<f32>) -> u32 {
+        self.angular_distance(a, b).0.to_bits()
+    }
+}
+
+impl Metric<&[f32]> for AngularMetric {
+    type Unit = u32;
+
+    fn distance(&self, a: &&[f32], b: &&[f32]) -> u32 {
+        self.angular_distance(a, b).0.to_bits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testutil::random_vectors, AnnoyAngular};
+
+    #[test]
+    fn test_angular_metric() -> anyhow::Result<()> {
+        let vectors = random_vectors(20, 8, 1);
+        let a = AnnoyAngular::from_vectors(&vectors, 1)?;
+        for i in 0..vectors.len() {
+            for j in 0..vectors.len() {
+                let expected = a.get_distance(i as u32, j as u32).0;
+                let d = f32::from_bits(AngularMetric.distance(&vectors[i], &vectors[j]));
+                assert!((d - expected).abs() < 1e-5, "{i} {j}: {d} != {expected}");
+            }
+        }
+
+        // Larger distances have larger units.
+        let (x, y, z) = (vec![1.0, 0.0], vec![1.0, 1.0], vec![-1.0, 0.0]);
+        assert!(AngularMetric.distance(&x, &y) < AngularMetric.distance(&x, &z));
+        assert_eq!(AngularMetric.distance(&x, &x), 0);
+        Ok(())
+    }
+}
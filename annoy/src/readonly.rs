@@ -0,0 +1,176 @@
+use std::path::Path;
+
+#[cfg(feature = "rayon")]
+use crate::{to_neighbors, DistanceUnit};
+use crate::{AngularDistance, AnnoyAngular, Neighbor};
+
+// ReadOnlyAnnoy is a loaded index for serving, from AnnoyAngular::open. It only has the methods
+// that read the index, so adding items to it, building or unloading it doesn't compile. Because
+// nothing can change it, it can also be shared between threads.
+#[derive(Debug)]
+pub struct ReadOnlyAnnoy {
+    index: AnnoyAngular,
+}
+
+// Every method takes &self and only runs queries and lookups, which Annoy implements as const
+// methods that read the (mmapped) nodes without any shared scratch state, so concurrent calls are
+// fine. Annoy's Python bindings rely on the same thing when they release the GIL around queries.
+unsafe impl Sync for ReadOnlyAnnoy {}
+
+impl AnnoyAngular {
+    // AnnoyAngular::open(fn, f) loads (mmaps) the index of f-dimensional vectors saved at fn for
+    // serving queries. See AnnoyAngular::load.
+    pub fn open(p: &Path, dimension: usize) -> anyhow::Result<ReadOnlyAnnoy> {
+        let mut index = AnnoyAngular::try_new(dimension)?;
+        index.load(p)?;
+        Ok(ReadOnlyAnnoy { index })
+    }
+}
+
+impl ReadOnlyAnnoy {
+    // a.get_nearest_by_item(i, n, search_k), as for AnnoyAngular.
+    pub fn get_nearest_by_item(
+        &self,
+        item: u32,
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<(Vec<u32>, Vec<f32>)> {
+        self.index.get_nearest_by_item(item, n, search_k)
+    }
+
+    // a.get_nearest_by_vector(v, n, search_k), as for AnnoyAngular.
+    pub fn get_nearest_by_vector(
+        &self,
+        vector: impl AsRef<[f32]>,
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<(Vec<u32>, Vec<f32>)> {
+        self.index.get_nearest_by_vector(vector, n, search_k)
+    }
+
+    // a.get_nearest_by_vector_into(v, n, search_k, results, distances), as for AnnoyAngular.
+    pub fn get_nearest_by_vector_into(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+        results: &mut Vec<u32>,
+        distances: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        self.index
+            .get_nearest_by_vector_into(vector, n, search_k, results, distances)
+    }
+
+    // a.nearest(v, search_k), as for AnnoyAngular.
+    pub fn nearest(&self, vector: &[f32], search_k: i32) -> anyhow::Result<Option<Neighbor>> {
+        self.index.nearest(vector, search_k)
+    }
+
+    // a.all_nearest(n, search_k), as for AnnoyAngular.
+    pub fn all_nearest(
+        &self,
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(u32, Vec<Neighbor>)>> + '_> {
+        self.index.all_nearest(n, search_k)
+    }
+
+    // a.par_all_nearest(n, search_k) is a.all_nearest(n, search_k) with the queries spread over
+    // rayon's current thread pool, collected in id order. It's only on ReadOnlyAnnoy because the
+    // queries share the index between threads, which needs it to be Sync.
+    #[cfg(feature = "rayon")]
+    pub fn par_all_nearest(
+        &self,
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<Vec<(u32, Vec<Neighbor>)>> {
+        use rayon::prelude::*;
+
+        // Call the ReadOnlyAnnoy methods rather than self.index's, so the closures capture the
+        // Sync &ReadOnlyAnnoy instead of a &AnnoyAngular.
+        (0..self.get_n_items())
+            .into_par_iter()
+            .filter(|&item| self.item_vector_ref(item).is_some())
+            .map(|item| {
+                let (results, distances) = self.get_nearest_by_item(item, n, search_k)?;
+                Ok((item, to_neighbors(results, distances, DistanceUnit::Annoy)))
+            })
+            .collect()
+    }
+
+    // a.get_item_vector(i), as for AnnoyAngular. Panics if i is past a.get_n_items().
+    pub fn get_item_vector(&self, item: u32) -> Vec<f32> {
+        self.index.get_item_vector(item)
+    }
+
+    // a.item_vector_ref(i), as for AnnoyAngular.
+    pub fn item_vector_ref(&self, item: u32) -> Option<&[f32]> {
+        self.index.item_vector_ref(item)
+    }
+
+    // a.get_distance(i, j), as for AnnoyAngular. Panics if i or j is past a.get_n_items().
+    pub fn get_distance(&self, i: u32, j: u32) -> AngularDistance {
+        self.index.get_distance(i, j)
+    }
+
+    // a.get_n_items() returns the number of items in the index.
+    pub fn get_n_items(&self) -> u32 {
+        self.index.get_n_items()
+    }
+
+    // a.get_n_trees() returns the number of trees in the index.
+    pub fn get_n_trees(&self) -> u32 {
+        self.index.get_n_trees()
+    }
+
+    // a.warm() reads the whole index into the page cache, as for AnnoyAngular.
+    pub fn warm(&self) {
+        self.index.warm()
+    }
+
+    // a.current_path() returns the file the index was opened from.
+    pub fn current_path(&self) -> &Path {
+        self.index
+            .current_path()
+            .expect("a loaded index has a path")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{random_vectors, temp_path};
+
+    #[test]
+    fn test_open() -> anyhow::Result<()> {
+        let path = temp_path("open");
+        let vectors = random_vectors(100, 8, 1);
+        let mut a = AnnoyAngular::from_vectors(&vectors, 10)?;
+        a.save(&path)?;
+
+        let index = AnnoyAngular::open(&path, 8)?;
+        assert_eq!(index.get_n_items(), 100);
+        assert_eq!(index.current_path(), path);
+        let expected = a.get_nearest_by_vector(&vectors[0], 10, -1)?;
+
+        // Queries from several threads at once see the same index.
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| index.get_nearest_by_vector(&vectors[0], 10, -1).unwrap()))
+                .collect();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap(), expected);
+            }
+        });
+
+        let all: Vec<(u32, Vec<Neighbor>)> =
+            index.all_nearest(5, -1)?.collect::<anyhow::Result<_>>()?;
+        assert_eq!(all.len(), 100);
+        #[cfg(feature = "rayon")]
+        assert_eq!(index.par_all_nearest(5, -1)?, all);
+
+        std::fs::remove_file(&path)?;
+        assert!(AnnoyAngular::open(&path, 8).is_err());
+        Ok(())
+    }
+}
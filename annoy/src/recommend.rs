@@ -0,0 +1,208 @@
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{labels_path, AnnoyAngular, AnnoyCosine, LabelStore};
+
+// RecommendationIndex is the "just use this" index for recommending similar items: each item is a
+// vector with a label (e.g. a product id), and queries return the labels of the most similar items
+// along with their cosine similarity, most similar first. It's an AnnoyCosine, so vectors don't
+// need to be normalized, plus a LabelStore kept next to the index file like LabeledAnnoy's. Use
+// AnnoyAngular directly for anything it doesn't cover.
+#[derive(Debug)]
+pub struct RecommendationIndex<T> {
+    index: AnnoyCosine,
+    labels: LabelStore<T>,
+    search_k: i32,
+    // The number of ids below the item count that have no label, counted when the index is built
+    // or loaded. Queries ask for this many extra results, since with_labels drops them.
+    holes: usize,
+}
+
+impl<T> RecommendationIndex<T> {
+    // RecommendationIndex::new(f) returns a new, empty index of f-dimensional vectors.
+    pub fn new(dimension: usize) -> Self {
+        Self {
+            index: AnnoyCosine::new(dimension),
+            labels: LabelStore::new(),
+            search_k: -1,
+            holes: 0,
+        }
+    }
+
+    // r.add_item(i, v, label) adds item i with vector v and label. Zero vectors have no direction,
+    // so they're rejected.
+    pub fn add_item(&mut self, item: u32, vector: &[f32], label: T) -> anyhow::Result<()> {
+        self.index.add_item(item, vector)?;
+        self.labels.insert(item, label);
+        Ok(())
+    }
+
+    // r.build(n_trees) builds a forest of n_trees trees, like AnnoyAngular::build.
+    pub fn build(&mut self, n_trees: i32) -> anyhow::Result<()> {
+        self.index.build(n_trees)?;
+        self.holes = self.count_holes();
+        Ok(())
+    }
+
+    // r.set_search_k(search_k) sets the search_k recommend and recommend_by_vector query with.
+    // It starts at -1, Annoy's default of n_trees * n.
+    pub fn set_search_k(&mut self, search_k: i32) {
+        self.search_k = search_k;
+    }
+
+    // r.recommend(i, n) returns the labels of the n items most similar to item i, not counting i
+    // itself, and their cosine similarities to it. It asks the index for n + 1 results plus one
+    // for every unlabeled id, so leaving those out still leaves n, unless there are fewer than n
+    // other items or the search (which is approximate) doesn't find them.
+    pub fn recommend(&self, item: u32, n: usize) -> anyhow::Result<Vec<(&T, f32)>> {
+        let (results, distances) = self.index.get_nearest_by_item(
+            item,
+            n.saturating_add(1).saturating_add(self.holes),
+            self.search_k,
+        )?;
+        Ok(self.with_labels(results, distances, Some(item), n))
+    }
+
+    // r.recommend_by_vector(v, n) returns the labels of the n items most similar to v and their
+    // cosine similarities to it. Like recommend, it asks for extra results to make up for
+    // unlabeled ids.
+    pub fn recommend_by_vector(&self, vector: &[f32], n: usize) -> anyhow::Result<Vec<(&T, f32)>> {
+        let (results, distances) = self.index.get_nearest_by_vector(
+            vector,
+            n.saturating_add(self.holes),
+            self.search_k,
+        )?;
+        Ok(self.with_labels(results, distances, None, n))
+    }
+
+    // r.label(i) returns the label of item i, if it was added.
+    pub fn label(&self, item: u32) -> Option<&T> {
+        self.labels.get(item)
+    }
+
+    // r.as_angular() gives access to the underlying angular index. Its vectors are normalized and
+    // its distances are angular.
+    pub fn as_angular(&self) -> &AnnoyAngular {
+        self.index.as_angular()
+    }
+
+    // Ids that were never added have no label (and a zero vector), so they're left out.
+    fn with_labels(
+        &self,
+        results: Vec<u32>,
+        distances: Vec<f32>,
+        exclude: Option<u32>,
+        n: usize,
+    ) -> Vec<(&T, f32)> {
+        results
+            .into_iter()
+            .zip(distances)
+            .filter(|&(id, _)| Some(id) != exclude)
+            .filter_map(|(id, distance)| Some((self.labels.get(id)?, 1.0 - distance)))
+            .take(n)
+            .collect()
+    }
+
+    fn count_holes(&self) -> usize {
+        (0..self.index.get_n_items())
+            .filter(|&item| self.labels.get(item).is_none())
+            .count()
+    }
+}
+
+impl<T: Serialize> RecommendationIndex<T> {
+    // r.save(fn) saves the index to fn and the labels to labels_path(fn).
+    pub fn save(&mut self, p: &Path) -> anyhow::Result<()> {
+        self.index.save(p)?;
+        self.labels.save(&labels_path(p))
+    }
+}
+
+impl<T: DeserializeOwned> RecommendationIndex<T> {
+    // r.load(fn) loads an index saved by RecommendationIndex::save, along with its labels. Unlike
+    // LabeledAnnoy::load, a missing labels file is an error rather than loading with no labels:
+    // recommendations are labels, so every query would quietly come back empty.
+    pub fn load(&mut self, p: &Path) -> anyhow::Result<()> {
+        let labels_path = labels_path(p);
+        let labels = LabelStore::load(&labels_path).map_err(|err| {
+            anyhow::anyhow!(
+                "RecommendationIndex::load failed: no labels at {}: {err}",
+                labels_path.display()
+            )
+        })?;
+        self.index.load(p)?;
+        self.labels = labels;
+        self.holes = self.count_holes();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_path;
+
+    #[test]
+    fn test_recommend() -> anyhow::Result<()> {
+        let path = temp_path("recommend");
+        let mut r = RecommendationIndex::new(2);
+        r.add_item(0, &[2.0, 0.0], "east".to_string())?;
+        r.add_item(1, &[3.0, 3.0], "northeast".to_string())?;
+        r.add_item(2, &[0.0, 1.0], "north".to_string())?;
+        r.add_item(3, &[-1.0, 0.0], "west".to_string())?;
+        r.build(-1)?;
+        r.save(&path)?;
+
+        let mut s = RecommendationIndex::<String>::new(2);
+        s.load(&path)?;
+        let recommended = s.recommend(0, 2)?;
+        assert_eq!(
+            recommended
+                .iter()
+                .map(|(l, _)| l.as_str())
+                .collect::<Vec<_>>(),
+            vec!["northeast", "north"]
+        );
+        assert!((recommended[0].1 - 0.5f32.sqrt()).abs() < 1e-5);
+        assert!(recommended[1].1.abs() < 1e-5);
+
+        let recommended = s.recommend_by_vector(&[-5.0, 0.1], 1)?;
+        assert_eq!(recommended[0].0, "west");
+        assert!(recommended[0].1 > 0.99);
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(labels_path(&path))?;
+
+        // Without the labels there's nothing to recommend, so loading fails.
+        r.save(&path)?;
+        std::fs::remove_file(labels_path(&path))?;
+        assert!(RecommendationIndex::<String>::new(2).load(&path).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_recommend_n() -> anyhow::Result<()> {
+        let mut r = RecommendationIndex::new(2);
+        for i in 0..20u32 {
+            // Every third id is a hole.
+            if i % 3 != 1 {
+                let theta = i as f32 * 0.1;
+                r.add_item(i, &[theta.cos(), theta.sin()], i)?;
+            }
+        }
+        r.build(10)?;
+        r.set_search_k(10_000);
+
+        // 13 items, so there are 12 others to recommend.
+        for n in [1, 5, 12] {
+            let recommended = r.recommend(0, n)?;
+            assert_eq!(recommended.len(), n);
+            assert!(recommended.iter().all(|&(&label, _)| label != 0));
+            assert_eq!(r.recommend_by_vector(&[1.0, 0.0], n)?.len(), n);
+        }
+        assert_eq!(r.recommend(0, 20)?.len(), 12);
+        Ok(())
+    }
+}
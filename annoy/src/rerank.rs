@@ -0,0 +1,121 @@
+use crate::{AnnoyAngular, Neighbor};
+
+impl AnnoyAngular {
+    // a.get_nearest_by_vector_with_vectors(v, n, search_k) is get_nearest_by_vector returning each
+    // neighbor as (id, distance, vector), with the item's stored vector copied out alongside it,
+    // e.g. to feed a re-ranking step. Each vector is copied once, straight from the index's
+    // storage (see a.item_vector_ref).
+    pub fn get_nearest_by_vector_with_vectors(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+    ) -> anyhow::Result<Vec<(u32, f32, Vec<f32>)>> {
+        let (results, distances) = self.get_nearest_by_vector(vector, n, search_k)?;
+        Ok(results
+            .into_iter()
+            .zip(distances)
+            .filter_map(|(id, distance)| Some((id, distance, self.item_vector_ref(id)?.to_vec())))
+            .collect())
+    }
+
+    // a.get_nearest_by_vector_reranked(v, n, search_k, oversample_factor, score) queries for
+    // n * oversample_factor candidates, scores each with score(id, vector), where vector is the
+    // item's stored vector, and returns the n with the lowest scores, lowest first. Each Neighbor's
+    // distance is its score.
+    //
+    // This puts an exact re-ranking step (e.g. a different metric, or the full-precision vectors
+    // the index was built from) on top of Annoy's approximate retrieval. The larger
+    // oversample_factor, the more of the true top n by score are among the candidates, at the cost
+    // of scoring more of them. Candidates with equal scores keep the order Annoy returned them in.
+    pub fn get_nearest_by_vector_reranked<F: Fn(u32, &[f32]) -> f32>(
+        &self,
+        vector: &[f32],
+        n: usize,
+        search_k: i32,
+        oversample_factor: usize,
+        score: F,
+    ) -> anyhow::Result<Vec<Neighbor>> {
+        anyhow::ensure!(
+            oversample_factor > 0,
+            "get_nearest_by_vector_reranked failed: oversample_factor must be at least 1"
+        );
+        let (results, _) =
+            self.get_nearest_by_vector(vector, n.saturating_mul(oversample_factor), search_k)?;
+        let mut neighbors: Vec<Neighbor> = results
+            .into_iter()
+            .filter_map(|id| {
+                let distance = score(id, self.item_vector_ref(id)?);
+                Some(Neighbor { id, distance })
+            })
+            .collect();
+        neighbors.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        neighbors.truncate(n);
+        Ok(neighbors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::random_vectors;
+
+    #[test]
+    fn test_get_nearest_by_vector_with_vectors() -> anyhow::Result<()> {
+        let vectors = random_vectors(200, 8, 1);
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+        let query = &random_vectors(1, 8, 2)[0];
+
+        let (results, distances) = a.get_nearest_by_vector(query, 10, -1)?;
+        let with_vectors = a.get_nearest_by_vector_with_vectors(query, 10, -1)?;
+        assert_eq!(with_vectors.len(), results.len());
+        for ((id, distance, vector), (&expected_id, &expected_distance)) in
+            with_vectors.iter().zip(results.iter().zip(&distances))
+        {
+            assert_eq!(*id, expected_id);
+            assert_eq!(*distance, expected_distance);
+            assert_eq!(vector, &vectors[*id as usize]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_nearest_by_vector_reranked() -> anyhow::Result<()> {
+        let vectors = random_vectors(200, 8, 1);
+        let a = AnnoyAngular::from_vectors(&vectors, 10)?;
+        let query = &random_vectors(1, 8, 2)[0];
+
+        // Re-ranking by Euclidean distance picks the candidates closest by that measure, and hands
+        // the closure the vectors the items were added with.
+        let euclidean = |v: &[f32]| -> f32 {
+            v.iter()
+                .zip(query)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt()
+        };
+        let reranked = a.get_nearest_by_vector_reranked(query, 5, 10_000, 4, |id, v| {
+            assert_eq!(v, &vectors[id as usize][..]);
+            euclidean(v)
+        })?;
+        let (candidates, _) = a.get_nearest_by_vector(query, 20, 10_000)?;
+        let mut expected: Vec<(u32, f32)> = candidates
+            .into_iter()
+            .map(|id| (id, euclidean(&vectors[id as usize])))
+            .collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+        expected.truncate(5);
+        assert_eq!(
+            reranked
+                .iter()
+                .map(|n| (n.id, n.distance))
+                .collect::<Vec<_>>(),
+            expected
+        );
+
+        assert!(a
+            .get_nearest_by_vector_reranked(query, 5, -1, 0, |_, _| 0.0)
+            .is_err());
+        Ok(())
+    }
+}
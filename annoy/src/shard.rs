@@ -0,0 +1,53 @@
+use crate::AnnoyAngular;
+
+// One result from merge_query. Item ids are only unique within an index, so each result also
+// records the position of the index (the shard) it came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShardNeighbor {
+    pub shard: usize,
+    pub id: u32,
+    pub distance: f32,
+}
+
+// merge_query(indexes, v, n, search_k) queries every index for its n closest items to v and
+// returns the n closest overall, closest first. search_k is passed through to each index.
+pub fn merge_query(
+    indexes: &[&AnnoyAngular],
+    vector: &[f32],
+    n: usize,
+    search_k: i32,
+) -> anyhow::Result<Vec<ShardNeighbor>> {
+    let mut neighbors = Vec::new();
+    for (shard, index) in indexes.iter().enumerate() {
+        let (ids, distances) = index.get_nearest_by_vector(vector, n, search_k)?;
+        neighbors.extend(
+            ids.into_iter()
+                .zip(distances)
+                .map(|(id, distance)| ShardNeighbor {
+                    shard,
+                    id,
+                    distance,
+                }),
+        );
+    }
+    neighbors.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    neighbors.truncate(n);
+    Ok(neighbors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_query() -> anyhow::Result<()> {
+        let a = AnnoyAngular::from_vectors(&[vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]], -1)?;
+        let b = AnnoyAngular::from_vectors(&[vec![0.0, 0.0, 1.0], vec![1.0, 0.1, 0.0]], -1)?;
+
+        let neighbors = merge_query(&[&a, &b], &[1.0, 0.0, 0.0], 3, -1)?;
+        let found: Vec<(usize, u32)> = neighbors.iter().map(|n| (n.shard, n.id)).collect();
+        assert_eq!(found, vec![(0, 0), (1, 1), (0, 1)]);
+        assert!(neighbors.windows(2).all(|w| w[0].distance <= w[1].distance));
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+// Reproducible random data for tests and benchmarks. Enabled by the `testutil` feature.
+use std::path::PathBuf;
+
+use crate::normalize;
+
+// temp_path(name) returns a path in the system's temporary directory for a test's index file.
+// The process id keeps concurrent test runs apart, and name keeps the tests of one run apart.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("annoy-rs-{}-{name}.ann", std::process::id()))
+}
+
+// random_vectors(n, dimension, seed) returns n vectors with components uniform in [-1, 1). The
+// same seed always gives the same vectors, on every platform. It uses xorshift64, which is fast
+// and dependency-free but not meant for anything beyond generating test data.
+pub fn random_vectors(n: usize, dimension: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = XorShift::new(seed);
+    (0..n)
+        .map(|_| (0..dimension).map(|_| rng.next_f32()).collect())
+        .collect()
+}
+
+// random_normalized_vectors(n, dimension, seed) is random_vectors scaled to unit length, so the
+// vectors point in random directions.
+pub fn random_normalized_vectors(n: usize, dimension: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = XorShift::new(seed);
+    (0..n)
+        .map(|_| loop {
+            let mut vector: Vec<f32> = (0..dimension).map(|_| rng.next_f32()).collect();
+            // Only the (vanishingly unlikely) zero vector can't be normalized.
+            if normalize(&mut vector).is_ok() {
+                break vector;
+            }
+        })
+        .collect()
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Zero is xorshift's one fixed point.
+        Self(if seed == 0 { 0x2545f4914f6cdd1d } else { seed })
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32 * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_vectors() {
+        let vectors = random_vectors(100, 8, 1);
+        assert_eq!(vectors.len(), 100);
+        assert!(vectors.iter().all(|v| v.len() == 8));
+        assert!(vectors.iter().flatten().all(|&x| (-1.0..1.0).contains(&x)));
+        assert_eq!(vectors, random_vectors(100, 8, 1));
+        assert_ne!(vectors, random_vectors(100, 8, 2));
+    }
+
+    #[test]
+    fn test_random_normalized_vectors() {
+        let vectors = random_normalized_vectors(100, 8, 1);
+        for vector in &vectors {
+            let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+            assert!((norm - 1.0).abs() < 1e-5);
+        }
+        assert_eq!(vectors, random_normalized_vectors(100, 8, 1));
+    }
+}
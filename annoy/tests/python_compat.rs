@@ -0,0 +1,75 @@
+// Indexes saved by the Python annoy package should load here unchanged: both are the same C++ core
+// writing the raw node array, with no header or format version to disagree on.
+//
+// fixtures/python_angular_3d.ann is what Python writes for
+//
+//     a = AnnoyIndex(3, "angular")
+//     a.add_item(0, [1, 0, 0])
+//     a.add_item(1, [0, 1, 0])
+//     a.add_item(2, [0, 0, 1])
+//     a.build(2)
+//     a.save("python_angular_3d.ann")
+//
+// Three items fit in a single leaf, so both trees are one root leaf listing every item and the
+// file doesn't depend on the random seed: three item nodes, the two roots and the copy of the
+// roots that build appends, 24 bytes each.
+use std::path::Path;
+
+use annoy::{read_metadata, testutil::temp_path, AnnoyAngular};
+
+fn fixture() -> &'static Path {
+    Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/python_angular_3d.ann"
+    ))
+}
+
+#[test]
+fn test_load_python_index() -> anyhow::Result<()> {
+    let mut a = AnnoyAngular::new(3);
+    a.load(fixture())?;
+    a.verify()?;
+    assert_eq!(a.get_n_items(), 3);
+    assert_eq!(a.get_item_vector(0), vec![1.0, 0.0, 0.0]);
+    assert_eq!(a.get_item_vector(1), vec![0.0, 1.0, 0.0]);
+    assert_eq!(a.get_item_vector(2), vec![0.0, 0.0, 1.0]);
+
+    let (results, distances) = a.get_nearest_by_vector([1.0, 0.5, 0.0], 3, -1)?;
+    assert_eq!(results, vec![0, 1, 2]);
+    assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+    assert!((a.get_distance(0, 1).0 - 2f32.sqrt()).abs() < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn test_python_index_matches_rust_build() -> anyhow::Result<()> {
+    // Building the same index here gives the same bytes.
+    let path = temp_path("python-compat");
+    let mut a = AnnoyAngular::new(3);
+    a.add_item(0, &[1.0, 0.0, 0.0])?;
+    a.add_item(1, &[0.0, 1.0, 0.0])?;
+    a.add_item(2, &[0.0, 0.0, 1.0])?;
+    a.build(2)?;
+    a.save(&path)?;
+    assert_eq!(std::fs::read(&path)?, std::fs::read(fixture())?);
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn test_read_python_index_metadata() -> anyhow::Result<()> {
+    let metadata = read_metadata(fixture())?;
+    assert_eq!(metadata.dimension, 3);
+    assert_eq!(metadata.n_items, 3);
+    let mut a = AnnoyAngular::new(3);
+    a.load(fixture())?;
+    assert_eq!(metadata.n_trees, a.get_n_trees());
+    Ok(())
+}
+
+#[test]
+fn test_load_python_index_wrong_dimension() {
+    // 168 bytes isn't a whole number of 20 byte nodes.
+    let mut a = AnnoyAngular::new(2);
+    assert!(a.load(fixture()).is_err());
+}